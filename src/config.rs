@@ -1,9 +1,11 @@
 use itertools::Itertools;
+use nix::unistd::{Uid, User};
 use std::collections::HashMap;
 use std::fs::File;
+use std::hash::{Hash, Hasher};
 use std::io::Read;
 use std::{
-    fmt,
+    env, fmt,
     path::{Path, PathBuf},
 };
 
@@ -12,6 +14,10 @@ pub enum Error {
     ConfigNotFound,
     Io(std::io::Error),
     InvalidConfig(ParseError),
+    // The file is larger than the byte limit it was read with
+    ConfigTooLarge(PathBuf, u64),
+    // The file isn't valid UTF-8, most likely it isn't a config at all
+    InvalidUtf8(PathBuf),
 }
 
 #[derive(Debug, PartialEq)]
@@ -20,6 +26,15 @@ pub enum ParseError {
     UnknownSymbol(PathBuf, u32),
     InvalidModifier(PathBuf, u32),
     InvalidKeysym(PathBuf, u32),
+    InvalidAnnotation(PathBuf, u32),
+    MissingCommand(PathBuf, u32),
+    UndefinedVariable(PathBuf, u32),
+    InvalidChord(PathBuf, u32),
+    DuplicateModifier(PathBuf, u32),
+    EmptyToken(PathBuf, u32),
+    InvalidAlias(PathBuf, u32),
+    UndefinedAlias(PathBuf, u32),
+    InvalidDevice(PathBuf, u32),
 }
 
 impl From<std::io::Error> for Error {
@@ -34,10 +49,19 @@ impl From<std::io::Error> for Error {
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match &*self {
+        match self {
             Error::ConfigNotFound => "Config file not found.".fmt(f),
 
             Error::Io(io_err) => format!("I/O Error while parsing config file: {}", io_err).fmt(f),
+            Error::ConfigTooLarge(path, limit) => format!(
+                "Config file {:?} is larger than {} bytes, is it really a config file?",
+                path, limit
+            )
+            .fmt(f),
+            Error::InvalidUtf8(path) => {
+                format!("Config file {:?} is not valid UTF-8, is it really a config file?", path)
+                    .fmt(f)
+            }
             Error::InvalidConfig(parse_err) => match parse_err {
                 ParseError::UnknownSymbol(path, line_nr) => format!(
                     "Error parsing config file {:?}. Unknown symbol at line {}.",
@@ -54,12 +78,64 @@ impl fmt::Display for Error {
                     path, line_nr
                 )
                 .fmt(f),
+                ParseError::InvalidAnnotation(path, line_nr) => format!(
+                    "Error parsing config file {:?}. Invalid annotation at line {}.",
+                    path, line_nr
+                )
+                .fmt(f),
+                ParseError::MissingCommand(path, line_nr) => format!(
+                    "Error parsing config file {:?}. Keybinding without a command at line {}.",
+                    path, line_nr
+                )
+                .fmt(f),
+                ParseError::UndefinedVariable(path, line_nr) => format!(
+                    "Error parsing config file {:?}. Undefined environment variable at line {}.",
+                    path, line_nr
+                )
+                .fmt(f),
+                ParseError::InvalidChord(path, line_nr) => format!(
+                    "Error parsing config file {:?}. Invalid chord at line {}.",
+                    path, line_nr
+                )
+                .fmt(f),
+                ParseError::DuplicateModifier(path, line_nr) => format!(
+                    "Error parsing config file {:?}. Duplicate modifier at line {}.",
+                    path, line_nr
+                )
+                .fmt(f),
+                ParseError::InvalidAlias(path, line_nr) => format!(
+                    "Error parsing config file {:?}. Invalid alias at line {}.",
+                    path, line_nr
+                )
+                .fmt(f),
+                ParseError::UndefinedAlias(path, line_nr) => format!(
+                    "Error parsing config file {:?}. Alias of an undefined key or modifier at line {}.",
+                    path, line_nr
+                )
+                .fmt(f),
+                ParseError::InvalidDevice(path, line_nr) => format!(
+                    "Error parsing config file {:?}. Invalid device name at line {}.",
+                    path, line_nr
+                )
+                .fmt(f),
+                ParseError::EmptyToken(path, line_nr) => format!(
+                    "Error parsing config file {:?}. Missing key or modifier between '+' at line {}.",
+                    path, line_nr
+                )
+                .fmt(f),
             },
         }
     }
 }
 
 pub const IMPORT_STATEMENT: &str = "include";
+pub const ALIAS_STATEMENT: &str = "alias";
+pub const ANY_MODIFIER: &str = "any";
+pub const DEVICE_STATEMENT: &str = "device";
+pub const IGNORE_STATEMENT: &str = "ignore";
+
+// The time within which the taps of `ctrl*2` have to follow each other, unless set with `[timeout]`
+pub const DEFAULT_REPEAT_TIMEOUT_MS: u32 = 300;
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Config {
@@ -68,47 +144,168 @@ pub struct Config {
     pub imports: Vec<PathBuf>,
 }
 
+// Configs are small, anything much bigger is most likely a file included by mistake
+pub const MAX_CONFIG_SIZE: u64 = 256 * 1024;
+
+#[allow(dead_code)]
 pub fn load_file_contents(path: &Path) -> Result<String, Error> {
-    let mut file = File::open(path)?;
-    let mut contents = String::new();
-    file.read_to_string(&mut contents)?;
-    Ok(contents)
+    load_file_contents_with_limit(path, MAX_CONFIG_SIZE)
+}
+
+// Read at most `limit` bytes, so that something like `/dev/zero` is never read in full. The size
+// is checked before the encoding, a large binary file is reported as too large.
+pub fn load_file_contents_with_limit(path: &Path, limit: u64) -> Result<String, Error> {
+    let file = File::open(path)?;
+    let mut contents = Vec::new();
+    file.take(limit + 1).read_to_end(&mut contents)?;
+    if contents.len() as u64 > limit {
+        return Err(Error::ConfigTooLarge(path.to_path_buf(), limit));
+    }
+    String::from_utf8(contents).map_err(|_| Error::InvalidUtf8(path.to_path_buf()))
+}
+
+// The home directory `~` and `$HOME` expand to. Under pkexec the environment is the one of root,
+// so the home of the invoking user is looked up from PKEXEC_UID, like `perms::drop_privileges`
+// looks up the user.
+pub fn home_dir(pkexec_uid: Option<&str>) -> Option<String> {
+    match pkexec_uid {
+        Some(uid) => {
+            let user = User::from_uid(Uid::from_raw(uid.parse().ok()?)).ok()??;
+            Some(user.dir.to_string_lossy().into_owned())
+        }
+        None => env::var("HOME").ok(),
+    }
+}
+
+// Expand a leading `~` and any `$VAR` or `${VAR}` in an import path from the environment.
+// Returns None if a variable isn't set, rather than silently expanding it to nothing.
+fn expand_import_path(import_path: &str) -> Option<String> {
+    let mut expanded = String::new();
+    let mut rest = import_path;
+    let home = || home_dir(env::var("PKEXEC_UID").ok().as_deref());
+
+    if rest == "~" || rest.starts_with("~/") {
+        expanded.push_str(&home()?);
+        rest = &rest[1..];
+    }
+
+    while let Some(start) = rest.find('$') {
+        expanded.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+
+        let (name, remaining) = if let Some(braced) = rest.strip_prefix('{') {
+            match braced.find('}') {
+                Some(end) => (&braced[..end], &braced[end + 1..]),
+                None => ("", rest),
+            }
+        } else {
+            let end = rest.find(|c: char| !c.is_ascii_alphanumeric() && c != '_');
+            rest.split_at(end.unwrap_or(rest.len()))
+        };
+
+        // A lone `$` is kept as it is
+        if name.is_empty() {
+            expanded.push('$');
+            continue;
+        }
+        if name == "HOME" {
+            expanded.push_str(&home()?);
+        } else {
+            expanded.push_str(&env::var(name).ok()?);
+        }
+        rest = remaining;
+    }
+
+    expanded.push_str(rest);
+    Some(expanded)
 }
 
 impl Config {
-    pub fn get_imports(contents: &str) -> Result<Vec<PathBuf>, Error> {
+    pub fn get_imports(path: &Path, contents: &str) -> Result<Vec<PathBuf>, Error> {
         let mut imports = Vec::new();
-        for line in contents.lines() {
+        for (line_nr, line) in contents.lines().enumerate() {
             if line.split(' ').next().unwrap() == IMPORT_STATEMENT {
                 if let Some(import_path) = line.split(' ').nth(1) {
-                    imports.push(Path::new(import_path).to_path_buf());
+                    let import_path = expand_import_path(import_path).ok_or_else(|| {
+                        Error::InvalidConfig(ParseError::UndefinedVariable(
+                            path.to_path_buf(),
+                            line_nr as u32 + 1,
+                        ))
+                    })?;
+                    if import_path.contains(['*', '?', '[']) {
+                        imports.extend(Self::expand_glob(path, &import_path));
+                    } else {
+                        imports.push(Path::new(&import_path).to_path_buf());
+                    }
                 }
             }
         }
         Ok(imports)
     }
 
+    // Expand an import like `conf.d/*.swhkd` into the matching files, sorted by path.
+    // Relative patterns are resolved against the directory of the importing config.
+    fn expand_glob(path: &Path, pattern: &str) -> Vec<PathBuf> {
+        let pattern = match path.parent() {
+            Some(dir) => dir.join(pattern),
+            None => PathBuf::from(pattern),
+        };
+        let mut imports: Vec<PathBuf> = match glob::glob(&pattern.to_string_lossy()) {
+            Ok(paths) => paths.filter_map(Result::ok).filter(|import| import.is_file()).collect(),
+            Err(_) => vec![pattern.clone()],
+        };
+        if imports.is_empty() {
+            log::warn!("No config files match the include pattern {:?}", pattern);
+        }
+        imports.sort();
+        imports
+    }
+
     pub fn new(path: &Path) -> Result<Self, Error> {
-        let contents = load_file_contents(path)?;
-        let imports = Self::get_imports(&contents)?;
+        Self::with_limit(path, MAX_CONFIG_SIZE)
+    }
+
+    // Like `new`, but the file may be at most `limit` bytes
+    pub fn with_limit(path: &Path, limit: u64) -> Result<Self, Error> {
+        let contents = load_file_contents_with_limit(path, limit)?;
+        let imports = Self::get_imports(path, &contents)?;
         Ok(Config { path: path.to_path_buf(), contents, imports })
     }
 
-    pub fn load_to_configs(&self) -> Result<Vec<Self>, Error> {
+    // The imports that exist, the paths of the other ones are added to `missing`
+    pub fn load_to_configs(
+        &self,
+        missing: &mut Vec<PathBuf>,
+        limit: u64,
+    ) -> Result<Vec<Self>, Error> {
         let mut configs = Vec::new();
         for import in &self.imports {
-            configs.push(Self::new(import)?)
+            match Self::with_limit(import, limit) {
+                Ok(config) => configs.push(config),
+                Err(Error::ConfigNotFound) => {
+                    if !missing.contains(import) {
+                        missing.push(import.clone());
+                    }
+                }
+                Err(e) => return Err(e),
+            }
         }
         Ok(configs)
     }
 
-    pub fn load_and_merge(mut configs: Vec<Self>) -> Result<Vec<Self>, Error> {
+    // Merge the configs with everything they include, also returning the includes of files that
+    // don't exist. Each include may be at most `limit` bytes.
+    pub fn load_and_merge(
+        mut configs: Vec<Self>,
+        limit: u64,
+    ) -> Result<(Vec<Self>, Vec<PathBuf>), Error> {
+        let mut missing = Vec::new();
         let mut prev_count = 0;
         let mut current_count = configs.len();
         while prev_count != current_count {
             prev_count = configs.len();
             for config in configs.clone() {
-                for import in Self::load_to_configs(&config)? {
+                for import in Self::load_to_configs(&config, &mut missing, limit)? {
                     if !configs.contains(&import) {
                         configs.push(import);
                     }
@@ -116,60 +313,284 @@ impl Config {
             }
             current_count = configs.len();
         }
-        Ok(configs)
+        Ok((configs, missing))
+    }
+}
+
+// Render the include hierarchy of a config like `tree` does, e.g.
+// ```
+// swhkdrc
+// ├── common.swhkd
+// │   └── swhkdrc (cycle)
+// └── missing.swhkd (not found)
+// ```
+// Paths are shown relative to the directory of the root config. A file that was already shown
+// is marked as a duplicate instead of being expanded again.
+pub fn format_include_tree(root: &Path) -> Result<String, Error> {
+    fn display_path(path: &Path, root_dir: &Path) -> String {
+        path.strip_prefix(root_dir).unwrap_or(path).display().to_string()
+    }
+
+    fn format_imports(
+        config: &Config,
+        root_dir: &Path,
+        prefix: &str,
+        ancestors: &mut Vec<PathBuf>,
+        visited: &mut Vec<PathBuf>,
+        output: &mut String,
+    ) -> Result<(), Error> {
+        for (i, import) in config.imports.iter().enumerate() {
+            let is_last = i == config.imports.len() - 1;
+            let (branch, indent) =
+                if is_last { ("└── ", "    ") } else { ("├── ", "│   ") };
+            output.push_str(&format!("{}{}{}", prefix, branch, display_path(import, root_dir)));
+
+            if ancestors.contains(import) {
+                output.push_str(" (cycle)\n");
+                continue;
+            }
+            if visited.contains(import) {
+                output.push_str(" (duplicate)\n");
+                continue;
+            }
+            visited.push(import.clone());
+
+            let import_config = match Config::new(import) {
+                Ok(import_config) => import_config,
+                Err(Error::ConfigNotFound) => {
+                    output.push_str(" (not found)\n");
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            output.push('\n');
+
+            ancestors.push(import.clone());
+            let prefix = format!("{}{}", prefix, indent);
+            format_imports(&import_config, root_dir, &prefix, ancestors, visited, output)?;
+            ancestors.pop();
+        }
+        Ok(())
     }
+
+    let config = Config::new(root)?;
+    let root_dir = root.parent().unwrap_or_else(|| Path::new(""));
+    let mut output = format!("{}\n", display_path(root, root_dir));
+    let mut ancestors = vec![root.to_path_buf()];
+    let mut visited = vec![root.to_path_buf()];
+    format_imports(&config, root_dir, "", &mut ancestors, &mut visited, &mut output)?;
+    Ok(output)
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ParseOptions {
+    // Record a normalized form of each command next to the raw one, see `normalize_command`
+    pub normalize_commands: bool,
+    // Only accept keysyms with an explicit namespace like `key:a`, see `match_keysym`
+    pub strict_namespaces: bool,
+    // The largest config file that is read in bytes, `MAX_CONFIG_SIZE` if not set
+    pub max_config_size: Option<u64>,
 }
 
-pub fn load(path: &Path) -> Result<Vec<Hotkey>, Error> {
+impl ParseOptions {
+    fn config_size_limit(&self) -> u64 {
+        self.max_config_size.unwrap_or(MAX_CONFIG_SIZE)
+    }
+}
+
+// Parse the config at `path` together with everything it includes.
+// Hotkeys come in the order the configs are merged in, so when two configs bind the same keys
+// the daemon uses the one that comes first.
+#[allow(dead_code)]
+pub fn parse_config(path: &Path) -> Result<Vec<Hotkey>, Error> {
+    parse_config_with_options(path, &ParseOptions::default())
+}
+
+pub fn parse_config_with_options(
+    path: &Path,
+    options: &ParseOptions,
+) -> Result<Vec<Hotkey>, Error> {
+    match parse_config_lenient(path, options)? {
+        (hotkeys, missing) if missing.is_empty() => Ok(hotkeys),
+        _ => Err(Error::ConfigNotFound),
+    }
+}
+
+// Like `parse_config`, but includes that point to files which don't exist are skipped. Their
+// paths are returned next to the hotkeys of the configs that were found.
+pub fn parse_config_lenient(
+    path: &Path,
+    options: &ParseOptions,
+) -> Result<(Vec<Hotkey>, Vec<PathBuf>), Error> {
+    let limit = options.config_size_limit();
+    let (configs, missing) = Config::load_and_merge(vec![Config::with_limit(path, limit)?], limit)?;
+    Ok((parse_configs(configs, options)?, missing))
+}
+
+// The includes of the config at `root` and everything it includes that point to files which
+// don't exist
+pub fn unresolved_includes(root: &Path) -> Result<Vec<PathBuf>, Error> {
+    Ok(Config::load_and_merge(vec![Config::new(root)?], MAX_CONFIG_SIZE)?.1)
+}
+
+fn parse_configs(configs: Vec<Config>, options: &ParseOptions) -> Result<Vec<Hotkey>, Error> {
     let mut hotkeys = Vec::new();
-    let configs = vec![Config::new(path)?];
-    for config in Config::load_and_merge(configs)? {
-        for hotkey in parse_contents(path.to_path_buf(), config.contents)? {
+    // The aliases of all configs are gathered first, so that an alias defined in an included
+    // config can be used in the config that includes it, and the other way round
+    let mut state = ParseState { gathering_aliases: true, ..Default::default() };
+    for config in &configs {
+        parse_contents_with_state(
+            config.path.clone(),
+            config.contents.clone(),
+            options,
+            &mut state,
+        )?;
+    }
+    state.gathering_aliases = false;
+    for config in configs {
+        for hotkey in parse_contents_with_state(config.path, config.contents, options, &mut state)?
+        {
             if !hotkeys.contains(&hotkey) {
                 hotkeys.push(hotkey);
             }
         }
     }
+    // An `ignore` in any of the configs removes the keybinding from all of them
+    hotkeys.retain(|hotkey| !state.is_ignored(hotkey));
     Ok(hotkeys)
 }
 
+// What is collected while reading a config and the configs it includes
+#[derive(Debug, Default)]
+struct ParseState {
+    // The name of each alias defined so far and the key or modifier it stands for.
+    // Later definitions replace earlier ones with the same name.
+    aliases: HashMap<String, String>,
+    // The keybindings (or chords) of `ignore` statements
+    ignored: Vec<Chord>,
+    // While set, only alias statements are read
+    gathering_aliases: bool,
+}
+
+impl ParseState {
+    fn is_ignored(&self, hotkey: &Hotkey) -> bool {
+        let chord = hotkey.chord.clone().unwrap_or_else(|| Chord(vec![hotkey.keybinding.clone()]));
+        self.ignored.contains(&chord)
+    }
+}
+
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct KeyBinding {
-    pub keysym: evdev::Key,
+    // None for a binding made of modifiers only, which fires when they are tapped
+    #[cfg_attr(feature = "serde", serde(serialize_with = "serialize_keysym"))]
+    pub keysym: Option<evdev::Key>,
     pub modifiers: Vec<Modifier>,
     pub send: bool,
     pub on_release: bool,
+    // Set by the `any` modifier, the binding then fires no matter which modifiers are held
+    pub match_any_modifier: bool,
+    // How often a lone modifier has to be tapped in a row, 2 for `ctrl*2`
+    pub repeat: u32,
+}
+
+// Keybindings are equal when they are written the same, up to the order of their modifiers.
+// Both comparing and hashing go through `normalized`, so `Eq` and `Hash` agree and stay transitive.
+// That `shift` also fires for `lshift`, or `any` for everything, is left to `matches_modifiers`.
+impl KeyBinding {
+    fn identity(&self) -> (Option<evdev::Key>, Vec<Modifier>, bool, bool, bool, u32) {
+        (
+            self.keysym,
+            self.normalized().modifiers,
+            self.send,
+            self.on_release,
+            self.match_any_modifier,
+            self.repeat,
+        )
+    }
 }
 
 impl PartialEq for KeyBinding {
     fn eq(&self, other: &Self) -> bool {
-        self.keysym == other.keysym
-            && self.modifiers.iter().all(|modifier| other.modifiers.contains(modifier))
-            && self.modifiers.len() == other.modifiers.len()
-            && self.send == other.send
-            && self.on_release == other.on_release
+        self.identity() == other.identity()
+    }
+}
+
+impl Eq for KeyBinding {}
+
+impl Hash for KeyBinding {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.identity().hash(state);
     }
 }
 
+// Keybindings pressed one after the other, written as `super + c ; super + k`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Chord(pub Vec<KeyBinding>);
+
 pub trait Prefix {
     fn send(self) -> Self;
     fn on_release(self) -> Self;
 }
 
 pub trait Value {
-    fn keysym(&self) -> evdev::Key;
+    fn keysym(&self) -> Option<evdev::Key>;
     fn modifiers(&self) -> Vec<Modifier>;
     fn is_send(&self) -> bool;
     fn is_on_release(&self) -> bool;
+    fn is_match_any_modifier(&self) -> bool;
 }
 
 impl KeyBinding {
     pub fn new(keysym: evdev::Key, modifiers: Vec<Modifier>) -> Self {
-        KeyBinding { keysym, modifiers, send: false, on_release: false }
+        KeyBinding {
+            keysym: Some(keysym),
+            modifiers,
+            send: false,
+            on_release: false,
+            match_any_modifier: false,
+            repeat: 1,
+        }
     }
-    pub fn on_release(mut self) -> Self {
-        self.on_release = true;
-        self
+
+    pub fn modifier_only(modifiers: Vec<Modifier>) -> Self {
+        KeyBinding {
+            keysym: None,
+            modifiers,
+            send: false,
+            on_release: false,
+            match_any_modifier: false,
+            repeat: 1,
+        }
+    }
+
+    // Whether the keybinding fires while exactly the `held` modifiers are held. A generic modifier
+    // like `shift` is held by either of its sides, but every modifier of the keybinding is matched
+    // with a different held one, so `shift + alt` doesn't fire for `lshift + rshift`.
+    pub fn matches_modifiers(&self, held: &[Modifier]) -> bool {
+        fn assign(wanted: &[Modifier], held: &[Modifier]) -> bool {
+            match wanted.split_first() {
+                None => held.is_empty(),
+                Some((modifier, rest)) => (0..held.len()).any(|i| {
+                    modifier.matches(held[i]) && {
+                        let mut remaining = held.to_vec();
+                        remaining.remove(i);
+                        assign(rest, &remaining)
+                    }
+                }),
+            }
+        }
+
+        self.match_any_modifier || assign(&self.modifiers, held)
+    }
+
+    // The same keybinding with its modifiers sorted into a stable order and deduplicated
+    pub fn normalized(&self) -> Self {
+        let mut keybinding = self.clone();
+        keybinding.modifiers.sort();
+        keybinding.modifiers.dedup();
+        keybinding
     }
 }
 
@@ -185,7 +606,7 @@ impl Prefix for KeyBinding {
 }
 
 impl Value for KeyBinding {
-    fn keysym(&self) -> evdev::Key {
+    fn keysym(&self) -> Option<evdev::Key> {
         self.keysym
     }
     fn modifiers(&self) -> Vec<Modifier> {
@@ -197,29 +618,143 @@ impl Value for KeyBinding {
     fn is_on_release(&self) -> bool {
         self.on_release
     }
+    fn is_match_any_modifier(&self) -> bool {
+        self.match_any_modifier
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Hotkey {
     pub keybinding: KeyBinding,
     pub command: String,
+    // Minimum time in milliseconds between two triggers of this hotkey
+    pub cooldown_ms: Option<u32>,
+    pub route: Route,
+    // Only used for analysis, the raw command is what gets run
+    pub normalized_command: Option<String>,
+    // A sticky binding stays active for repeated presses until the cancel key is pressed
+    pub sticky: bool,
+    #[cfg_attr(feature = "serde", serde(serialize_with = "serialize_key"))]
+    pub cancel_key: evdev::Key,
+    // Only fire for the device with this name, None for all devices
+    pub device: Option<String>,
+    // The whole sequence for a chord, `keybinding` is then its last keybinding
+    pub chord: Option<Chord>,
+    // Time in milliseconds within which the taps of a repeated modifier (`ctrl*2`) have to follow
+    // each other, None for `DEFAULT_REPEAT_TIMEOUT_MS`
+    pub timeout_ms: Option<u32>,
 }
 
-#[derive(Debug, PartialEq, Eq, Copy, Clone, Hash)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub enum Modifier {
     Super,
     Alt,
     Control,
     Shift,
+    LeftAlt,
+    RightAlt,
+    LeftControl,
+    RightControl,
+    LeftShift,
+    RightShift,
+}
+
+impl Modifier {
+    // Collapse a left or right variant into the modifier that stands for either side
+    pub fn generic(self) -> Self {
+        match self {
+            Modifier::LeftAlt | Modifier::RightAlt => Modifier::Alt,
+            Modifier::LeftControl | Modifier::RightControl => Modifier::Control,
+            Modifier::LeftShift | Modifier::RightShift => Modifier::Shift,
+            modifier => modifier,
+        }
+    }
+
+    // A generic modifier like `shift` matches both of its sides, while `lshift` and `rshift`
+    // only match themselves and the generic one
+    pub fn matches(self, other: Self) -> bool {
+        self == other || self == other.generic() || self.generic() == other
+    }
+}
+
+// Where the command of a hotkey should be run
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum Route {
+    // Hand the command to the swhks server
+    #[default]
+    Swhks,
+    // Run the command from the daemon itself
+    Direct,
+}
+
+// evdev keys aren't serializable, they are written out by name (`KEY_A`) instead. Raw keycodes
+// like `code:700` that evdev has no name for are written out as the number.
+#[cfg(feature = "serde")]
+pub fn serialize_key<S: serde::Serializer>(
+    key: &evdev::Key,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let name = format!("{:?}", key);
+    if name.starts_with("unknown key") {
+        serializer.serialize_u16(key.code())
+    } else {
+        serializer.serialize_str(&name)
+    }
+}
+
+#[cfg(feature = "serde")]
+fn serialize_keysym<S: serde::Serializer>(
+    keysym: &Option<evdev::Key>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match keysym {
+        Some(key) => serialize_key(key, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+// The hotkeys as pretty-printed JSON, to see how a config was understood
+#[cfg(feature = "serde")]
+pub fn hotkeys_to_json(hotkeys: &[Hotkey]) -> String {
+    serde_json::to_string_pretty(hotkeys).unwrap()
 }
 
 impl Hotkey {
     pub fn from_keybinding(keybinding: KeyBinding, command: String) -> Self {
-        Hotkey { keybinding, command }
+        Hotkey {
+            keybinding,
+            command,
+            cooldown_ms: None,
+            route: Route::default(),
+            normalized_command: None,
+            sticky: false,
+            cancel_key: evdev::Key::KEY_ESC,
+            device: None,
+            chord: None,
+            timeout_ms: None,
+        }
+    }
+    // Whether the hotkey fires for events from the device with this name
+    pub fn fires_on(&self, device: &str) -> bool {
+        self.device.as_deref().is_none_or(|name| name == device)
     }
     #[cfg(test)]
     pub fn new(keysym: evdev::Key, modifiers: Vec<Modifier>, command: String) -> Self {
-        Hotkey { keybinding: KeyBinding::new(keysym, modifiers), command }
+        Hotkey {
+            keybinding: KeyBinding::new(keysym, modifiers),
+            command,
+            cooldown_ms: None,
+            route: Route::default(),
+            normalized_command: None,
+            sticky: false,
+            cancel_key: evdev::Key::KEY_ESC,
+            device: None,
+            chord: None,
+            timeout_ms: None,
+        }
     }
 }
 
@@ -235,7 +770,7 @@ impl Prefix for Hotkey {
 }
 
 impl Value for &Hotkey {
-    fn keysym(&self) -> evdev::Key {
+    fn keysym(&self) -> Option<evdev::Key> {
         self.keybinding.keysym
     }
     fn modifiers(&self) -> Vec<Modifier> {
@@ -247,9 +782,36 @@ impl Value for &Hotkey {
     fn is_on_release(&self) -> bool {
         self.keybinding.on_release
     }
+    fn is_match_any_modifier(&self) -> bool {
+        self.keybinding.match_any_modifier
+    }
 }
 
+#[allow(dead_code)]
 pub fn parse_contents(path: PathBuf, contents: String) -> Result<Vec<Hotkey>, Error> {
+    parse_contents_with_options(path, contents, &ParseOptions::default())
+}
+
+#[allow(dead_code)]
+pub fn parse_contents_with_options(
+    path: PathBuf,
+    contents: String,
+    options: &ParseOptions,
+) -> Result<Vec<Hotkey>, Error> {
+    let mut state = ParseState::default();
+    let mut hotkeys = parse_contents_with_state(path, contents, options, &mut state)?;
+    hotkeys.retain(|hotkey| !state.is_ignored(hotkey));
+    Ok(hotkeys)
+}
+
+// Aliases and ignored keybindings in `contents` are added to `state`, the hotkeys are returned
+// without applying the ignores, since these can also come from other configs
+fn parse_contents_with_state(
+    path: PathBuf,
+    contents: String,
+    options: &ParseOptions,
+    state: &mut ParseState,
+) -> Result<Vec<Hotkey>, Error> {
     let key_to_evdev_key: HashMap<&str, evdev::Key> = HashMap::from([
         ("q", evdev::Key::KEY_Q),
         ("w", evdev::Key::KEY_W),
@@ -290,6 +852,7 @@ pub fn parse_contents(path: PathBuf, contents: String) -> Result<Vec<Hotkey>, Er
         ("escape", evdev::Key::KEY_ESC),
         ("backspace", evdev::Key::KEY_BACKSPACE),
         ("capslock", evdev::Key::KEY_CAPSLOCK),
+        ("caps_lock", evdev::Key::KEY_CAPSLOCK),
         ("return", evdev::Key::KEY_ENTER),
         ("enter", evdev::Key::KEY_ENTER),
         ("tab", evdev::Key::KEY_TAB),
@@ -315,6 +878,33 @@ pub fn parse_contents(path: PathBuf, contents: String) -> Result<Vec<Hotkey>, Er
         ("kpminus", evdev::Key::KEY_KPMINUS),
         ("kpplusminus", evdev::Key::KEY_KPPLUSMINUS),
         ("kprightparen", evdev::Key::KEY_KPRIGHTPAREN),
+        ("kpplus", evdev::Key::KEY_KPPLUS),
+        ("kpslash", evdev::Key::KEY_KPSLASH),
+        ("kp_0", evdev::Key::KEY_KP0),
+        ("kp_1", evdev::Key::KEY_KP1),
+        ("kp_2", evdev::Key::KEY_KP2),
+        ("kp_3", evdev::Key::KEY_KP3),
+        ("kp_4", evdev::Key::KEY_KP4),
+        ("kp_5", evdev::Key::KEY_KP5),
+        ("kp_6", evdev::Key::KEY_KP6),
+        ("kp_7", evdev::Key::KEY_KP7),
+        ("kp_8", evdev::Key::KEY_KP8),
+        ("kp_9", evdev::Key::KEY_KP9),
+        ("kp_asterisk", evdev::Key::KEY_KPASTERISK),
+        ("kp_multiply", evdev::Key::KEY_KPASTERISK),
+        ("kp_dot", evdev::Key::KEY_KPDOT),
+        ("kp_decimal", evdev::Key::KEY_KPDOT),
+        ("kp_enter", evdev::Key::KEY_KPENTER),
+        ("kp_minus", evdev::Key::KEY_KPMINUS),
+        ("kp_subtract", evdev::Key::KEY_KPMINUS),
+        ("kp_plus", evdev::Key::KEY_KPPLUS),
+        ("kp_add", evdev::Key::KEY_KPPLUS),
+        ("kp_slash", evdev::Key::KEY_KPSLASH),
+        ("kp_divide", evdev::Key::KEY_KPSLASH),
+        ("kp_equal", evdev::Key::KEY_KPEQUAL),
+        ("kp_comma", evdev::Key::KEY_KPCOMMA),
+        ("numlock", evdev::Key::KEY_NUMLOCK),
+        ("num_lock", evdev::Key::KEY_NUMLOCK),
         ("minus", evdev::Key::KEY_MINUS),
         ("-", evdev::Key::KEY_MINUS),
         ("equal", evdev::Key::KEY_EQUAL),
@@ -338,6 +928,9 @@ pub fn parse_contents(path: PathBuf, contents: String) -> Result<Vec<Hotkey>, Er
         ("xf86audioplay", evdev::Key::KEY_PLAYPAUSE),
         ("xf86audioprev", evdev::Key::KEY_PREVIOUSSONG),
         ("xf86audiostop", evdev::Key::KEY_STOP),
+        ("xf86audiopause", evdev::Key::KEY_PAUSECD),
+        ("xf86audiorewind", evdev::Key::KEY_REWIND),
+        ("xf86audioforward", evdev::Key::KEY_FASTFORWARD),
         ("xf86monbrightnessdown", evdev::Key::KEY_BRIGHTNESSDOWN),
         (",", evdev::Key::KEY_COMMA),
         ("comma", evdev::Key::KEY_COMMA),
@@ -356,6 +949,7 @@ pub fn parse_contents(path: PathBuf, contents: String) -> Result<Vec<Hotkey>, Er
         ("bracketright", evdev::Key::KEY_RIGHTBRACE),
         (";", evdev::Key::KEY_SEMICOLON),
         ("scroll_lock", evdev::Key::KEY_SCROLLLOCK),
+        ("scrolllock", evdev::Key::KEY_SCROLLLOCK),
         ("semicolon", evdev::Key::KEY_SEMICOLON),
         ("'", evdev::Key::KEY_APOSTROPHE),
         ("apostrophe", evdev::Key::KEY_APOSTROPHE),
@@ -397,6 +991,19 @@ pub fn parse_contents(path: PathBuf, contents: String) -> Result<Vec<Hotkey>, Er
         ("f22", evdev::Key::KEY_F22),
         ("f23", evdev::Key::KEY_F23),
         ("f24", evdev::Key::KEY_F24),
+        ("button1", evdev::Key::BTN_LEFT),
+        ("button2", evdev::Key::BTN_MIDDLE),
+        ("button3", evdev::Key::BTN_RIGHT),
+        // button4 to button7 are scroll events in X11, evdev reports those as relative axes
+        ("button8", evdev::Key::BTN_SIDE),
+        ("button9", evdev::Key::BTN_EXTRA),
+        ("btn_left", evdev::Key::BTN_LEFT),
+        ("btn_right", evdev::Key::BTN_RIGHT),
+        ("btn_middle", evdev::Key::BTN_MIDDLE),
+        ("btn_side", evdev::Key::BTN_SIDE),
+        ("btn_extra", evdev::Key::BTN_EXTRA),
+        ("btn_forward", evdev::Key::BTN_FORWARD),
+        ("btn_back", evdev::Key::BTN_BACK),
     ]);
 
     let mod_to_mod_enum: HashMap<&str, Modifier> = HashMap::from([
@@ -407,113 +1014,134 @@ pub fn parse_contents(path: PathBuf, contents: String) -> Result<Vec<Hotkey>, Er
         ("alt", Modifier::Alt),
         ("mod1", Modifier::Alt),
         ("shift", Modifier::Shift),
+        ("lalt", Modifier::LeftAlt),
+        ("ralt", Modifier::RightAlt),
+        ("lctrl", Modifier::LeftControl),
+        ("rctrl", Modifier::RightControl),
+        ("lshift", Modifier::LeftShift),
+        ("rshift", Modifier::RightShift),
     ]);
 
-    let lines: Vec<&str> = contents.split('\n').collect();
-
-    // Go through each line, ignore comments and empty lines, mark lines starting with whitespace
-    // as commands, and mark the other lines as keysyms. Mark means storing a line's type and the
-    // line number in a vector.
-    let mut lines_with_types: Vec<(&str, u32)> = Vec::new();
-    for (line_number, line) in lines.iter().enumerate() {
-        if line.trim().starts_with('#')
-            || line.split(' ').next().unwrap() == IMPORT_STATEMENT
-            || line.trim().is_empty()
-        {
-            continue;
-        }
-        if line.starts_with(' ') || line.starts_with('\t') {
-            lines_with_types.push(("command", line_number as u32));
-        } else {
-            lines_with_types.push(("keysym", line_number as u32));
-        }
-    }
+    let actual_lines = join_lines(&contents);
 
     // Edge case: return a blank vector if no lines detected
-    if lines_with_types.is_empty() {
+    if actual_lines.is_empty() {
         return Ok(vec![]);
     }
 
-    let mut actual_lines: Vec<(&str, u32, String)> = Vec::new();
-
-    if contents.contains('\\') {
-        // Go through lines_with_types, and add the next line over and over until the current line no
-        // longer ends with backslash. (Only if the lines have the same type)
-        let mut current_line_type = lines_with_types[0].0;
-        let mut current_line_number = lines_with_types[0].1;
-        let mut current_line_string = String::new();
-        let mut continue_backslash;
-
-        for (line_type, line_number) in lines_with_types {
-            if line_type != current_line_type {
-                current_line_type = line_type;
-                current_line_number = line_number;
-                current_line_string = String::new();
-            }
-
-            let line_to_add = lines[line_number as usize].trim();
-            continue_backslash = line_to_add.ends_with('\\');
-
-            let line_to_add = line_to_add.strip_suffix('\\').unwrap_or(line_to_add);
-
-            current_line_string.push_str(line_to_add);
-
-            if !continue_backslash {
-                actual_lines.push((current_line_type, current_line_number, current_line_string));
-                current_line_type = line_type;
-                current_line_number = line_number;
-                current_line_string = String::new();
-            }
-        }
-    } else {
-        for (line_type, line_number) in lines_with_types {
-            actual_lines.push((
-                line_type,
-                line_number,
-                lines[line_number as usize].trim().to_string(),
-            ));
-        }
-    }
-
-    drop(lines);
-
     let mut hotkeys: Vec<Hotkey> = Vec::new();
+    // Set by a `device` statement for the bindings following it, up to the end of the file
+    let mut device: Option<String> = None;
 
     for (i, item) in actual_lines.iter().enumerate() {
         let line_type = item.0;
         let line_number = item.1;
         let line = &item.2;
 
-        if line_type != "keysym" {
+        if state.gathering_aliases
+            && !(line_type == "statement" && line.split(' ').next() == Some(ALIAS_STATEMENT))
+        {
             continue;
         }
-
-        let next_line = actual_lines.get(i + 1);
-        if next_line.is_none() {
-            break;
+        if line_type == "statement" {
+            match line.split(' ').next().unwrap() {
+                DEVICE_STATEMENT => device = parse_device(path.clone(), line, line_number + 1)?,
+                IGNORE_STATEMENT => {
+                    let keybindings = line.strip_prefix(IGNORE_STATEMENT).unwrap();
+                    let keybindings = keybindings.split('#').next().unwrap();
+                    for keybinding in extract_curly_brace(keybindings) {
+                        state.ignored.push(parse_chord(
+                            path.clone(),
+                            &keybinding,
+                            line_number + 1,
+                            &key_to_evdev_key,
+                            &mod_to_mod_enum,
+                            options,
+                            &state.aliases,
+                        )?);
+                    }
+                }
+                _ => parse_alias(
+                    path.clone(),
+                    line,
+                    line_number + 1,
+                    &key_to_evdev_key,
+                    &mod_to_mod_enum,
+                    options,
+                    &mut state.aliases,
+                )?,
+            }
+            continue;
         }
-        let next_line = next_line.unwrap();
-
-        if next_line.0 != "command" {
-            continue; // this should ignore keysyms that are not followed by a command
+        if line_type != "keysym" {
+            continue;
         }
 
-        let extracted_keys = extract_curly_brace(line);
+        // Every keybinding has to be followed by a command
+        let next_line = match actual_lines.get(i + 1) {
+            Some(next_line) if next_line.0 == "command" => next_line,
+            _ => {
+                return Err(Error::InvalidConfig(ParseError::MissingCommand(path, line_number + 1)))
+            }
+        };
+
+        let (line, annotations) = parse_annotations(
+            path.clone(),
+            line,
+            line_number + 1,
+            &key_to_evdev_key,
+            options,
+            &state.aliases,
+        )?;
+        let extracted_keys = extract_curly_brace(&line);
         let extracted_commands = extract_curly_brace(&next_line.2);
 
         'hotkey_parse: for (key, command) in extracted_keys.iter().zip(extracted_commands.iter()) {
-            let keybinding = parse_keybind(
+            let chord = parse_chord(
                 path.clone(),
                 key,
                 line_number + 1,
                 &key_to_evdev_key,
                 &mod_to_mod_enum,
+                options,
+                &state.aliases,
             )?;
-            let hotkey = Hotkey::from_keybinding(keybinding, command.to_string());
+            let keybinding = chord.0.last().unwrap().clone();
+            let mut hotkey = Hotkey::from_keybinding(keybinding, command.to_string());
+            if chord.0.len() > 1 {
+                hotkey.chord = Some(chord);
+            } else if annotations.sticky {
+                // Only the last step of a chord can stay active
+                return Err(Error::InvalidConfig(ParseError::InvalidAnnotation(
+                    path,
+                    line_number + 1,
+                )));
+            }
+            hotkey.cooldown_ms = annotations.cooldown_ms;
+            hotkey.route = annotations.route.unwrap_or_default();
+            hotkey.sticky = annotations.sticky;
+            hotkey.device = device.clone();
+            // A timeout only makes sense between the taps of a repeated modifier
+            if annotations.timeout_ms.is_some() && hotkey.keybinding.repeat == 1 {
+                return Err(Error::InvalidConfig(ParseError::InvalidAnnotation(
+                    path,
+                    line_number + 1,
+                )));
+            }
+            hotkey.timeout_ms = annotations.timeout_ms;
+            if let Some(cancel_key) = annotations.cancel_key {
+                hotkey.cancel_key = cancel_key;
+            }
+            if options.normalize_commands {
+                hotkey.normalized_command = Some(normalize_command(&hotkey.command));
+            }
 
             // Ignore duplicate hotkeys
             for i in hotkeys.iter() {
-                if i.keybinding == hotkey.keybinding {
+                if i.keybinding == hotkey.keybinding
+                    && i.chord == hotkey.chord
+                    && i.device == hotkey.device
+                {
                     continue 'hotkey_parse;
                 }
             }
@@ -525,6 +1153,336 @@ pub fn parse_contents(path: PathBuf, contents: String) -> Result<Vec<Hotkey>, Er
     Ok(hotkeys)
 }
 
+// Lowercase the binary name of a command, so that `Firefox` and `firefox` compare equal
+pub fn normalize_command(command: &str) -> String {
+    let command = command.trim();
+    match command.find(char::is_whitespace) {
+        Some(end) => format!("{}{}", command[..end].to_lowercase(), &command[end..]),
+        None => command.to_lowercase(),
+    }
+}
+
+// The command used to compare hotkeys, the normalized one if the hotkey has one
+fn comparable_command(hotkey: &Hotkey) -> &str {
+    hotkey.normalized_command.as_deref().unwrap_or(&hotkey.command)
+}
+
+// Find pairs of hotkeys with different keybindings that run the same command.
+#[allow(dead_code)]
+pub fn find_duplicate_commands(hotkeys: &[Hotkey]) -> Vec<(Hotkey, Hotkey)> {
+    let mut duplicates = Vec::new();
+    for (i, hotkey) in hotkeys.iter().enumerate() {
+        for other in &hotkeys[i + 1..] {
+            if hotkey.keybinding != other.keybinding
+                && comparable_command(hotkey) == comparable_command(other)
+            {
+                duplicates.push((hotkey.clone(), other.clone()));
+            }
+        }
+    }
+    duplicates
+}
+
+// Find pairs of hotkeys with the same keybinding but different commands, where only the first one
+// will ever fire. Like `find_duplicate_commands`, the normalized commands are compared if present.
+// Hotkeys scoped to different devices or ending different chords don't conflict.
+pub fn find_conflicts(hotkeys: &[Hotkey]) -> Vec<(Hotkey, Hotkey)> {
+    let mut conflicts = Vec::new();
+    for (i, hotkey) in hotkeys.iter().enumerate() {
+        for other in &hotkeys[i + 1..] {
+            if hotkey.keybinding == other.keybinding
+                && hotkey.chord == other.chord
+                && hotkey.device == other.device
+                && comparable_command(hotkey) != comparable_command(other)
+            {
+                conflicts.push((hotkey.clone(), other.clone()));
+            }
+        }
+    }
+    conflicts
+}
+
+// Go through each line, ignore comments, empty lines and includes, mark lines starting with
+// whitespace as commands, statements such as `alias` as statements, and the other lines as
+// keysyms. Lines ending with a backslash are joined with the lines following them (only if the
+// lines have the same type).
+// Each joined line keeps the (zero based) number of its first physical line.
+pub fn join_lines(contents: &str) -> Vec<(&'static str, u32, String)> {
+    let mut joined_lines = Vec::new();
+    let mut current_line: Option<(&'static str, u32, String)> = None;
+
+    for (line_number, line) in contents.split('\n').enumerate() {
+        if line.trim().starts_with('#')
+            || line.split(' ').next().unwrap() == IMPORT_STATEMENT
+            || line.trim().is_empty()
+        {
+            continue;
+        }
+        let line_type = if line.starts_with(' ') || line.starts_with('\t') {
+            "command"
+        } else if [ALIAS_STATEMENT, DEVICE_STATEMENT, IGNORE_STATEMENT]
+            .contains(&line.split(' ').next().unwrap())
+        {
+            "statement"
+        } else {
+            "keysym"
+        };
+
+        let line_to_add = line.trim();
+        let continue_backslash = line_to_add.ends_with('\\');
+        let mut line_to_add = line_to_add.strip_suffix('\\').unwrap_or(line_to_add);
+        // The text of a command is kept as written, apart from its indentation. The parts of a
+        // continued command are joined with exactly one space, whatever spacing was around the
+        // backslash.
+        if line_type == "command" {
+            line_to_add = line_to_add.trim_end();
+        }
+
+        match &mut current_line {
+            Some((current_line_type, _, current_line_string))
+                if *current_line_type == line_type =>
+            {
+                if line_type == "command"
+                    && !current_line_string.is_empty()
+                    && !line_to_add.is_empty()
+                {
+                    current_line_string.push(' ');
+                }
+                current_line_string.push_str(line_to_add)
+            }
+            _ => current_line = Some((line_type, line_number as u32, line_to_add.to_string())),
+        }
+
+        if !continue_backslash {
+            joined_lines.extend(current_line.take());
+        }
+    }
+
+    // A backslash on the last line has nothing to join, keep what came before it
+    joined_lines.extend(current_line.map(|(line_type, line_number, line)| {
+        (line_type, line_number, line.trim_end().to_string())
+    }));
+    joined_lines
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Annotations {
+    pub cooldown_ms: Option<u32>,
+    pub route: Option<Route>,
+    pub sticky: bool,
+    pub cancel_key: Option<evdev::Key>,
+    pub timeout_ms: Option<u32>,
+}
+
+// Split the trailing annotations off a keybinding line.
+// Annotations are written as `[name=value]` after the keybinding, e.g.
+// ```
+// super + Print [cooldown=300] [via=direct]
+//     grim
+// ```
+// Flags like `[sticky]` have no value.
+pub fn parse_annotations(
+    path: PathBuf,
+    line: &str,
+    line_nr: u32,
+    key_to_evdev_key: &HashMap<&str, evdev::Key>,
+    options: &ParseOptions,
+    aliases: &HashMap<String, String>,
+) -> Result<(String, Annotations), Error> {
+    let mut remaining = line.split('#').next().unwrap().trim_end();
+    let mut annotations = Annotations::default();
+    let mut seen = Vec::new();
+
+    while remaining.ends_with(']') {
+        let start = match remaining.rfind('[') {
+            Some(start) => start,
+            None => break,
+        };
+        let annotation = &remaining[start + 1..remaining.len() - 1];
+        let (name, value) = match annotation.split_once('=') {
+            Some((name, value)) => (name.trim(), Some(value.trim())),
+            None => (annotation.trim(), None),
+        };
+
+        // Leave things like `super + [` or `super + ]` to the keysym parser
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphabetic() || c == '_') {
+            break;
+        }
+
+        let invalid_annotation =
+            || Error::InvalidConfig(ParseError::InvalidAnnotation(path.clone(), line_nr));
+        // Each annotation can be given once, `[via=direct] [via=swhks]` has no clear meaning
+        if seen.contains(&name) {
+            return Err(invalid_annotation());
+        }
+        seen.push(name);
+        match (name, value) {
+            ("cooldown", Some(value)) => {
+                let cooldown_ms = value.parse::<u32>().ok().filter(|cooldown_ms| *cooldown_ms > 0);
+                annotations.cooldown_ms = Some(cooldown_ms.ok_or_else(invalid_annotation)?);
+            }
+            ("via", Some(value)) => {
+                annotations.route = Some(match value.to_lowercase().as_str() {
+                    "swhks" => Route::Swhks,
+                    "direct" => Route::Direct,
+                    _ => return Err(invalid_annotation()),
+                });
+            }
+            ("timeout", Some(value)) => {
+                let timeout_ms = value.parse::<u32>().ok().filter(|timeout_ms| *timeout_ms > 0);
+                annotations.timeout_ms = Some(timeout_ms.ok_or_else(invalid_annotation)?);
+            }
+            ("sticky", None) => annotations.sticky = true,
+            ("cancel", Some(value)) => {
+                let value = value.to_lowercase();
+                let value = aliases.get(&value).unwrap_or(&value);
+                let cancel_key = match_keysym(value, key_to_evdev_key, options.strict_namespaces);
+                annotations.cancel_key = Some(cancel_key.ok_or_else(invalid_annotation)?);
+            }
+            _ => return Err(invalid_annotation()),
+        }
+
+        remaining = remaining[..start].trim_end();
+    }
+
+    // A cancel key only makes sense for a binding that stays active
+    if annotations.cancel_key.is_some() && !annotations.sticky {
+        return Err(Error::InvalidConfig(ParseError::InvalidAnnotation(path, line_nr)));
+    }
+
+    Ok((remaining.to_string(), annotations))
+}
+
+// Parse a `device "<name>"` statement into the device the following bindings are scoped to.
+// The quotes can be left out if the name has no spaces, a `device` without a name goes back to
+// all devices.
+fn parse_device(path: PathBuf, line: &str, line_nr: u32) -> Result<Option<String>, Error> {
+    let invalid_device = || Error::InvalidConfig(ParseError::InvalidDevice(path.clone(), line_nr));
+
+    let name = line.strip_prefix(DEVICE_STATEMENT).unwrap().trim();
+    let name = match name.strip_prefix('"') {
+        Some(quoted) => match quoted.split_once('"') {
+            Some((name, rest))
+                if !name.is_empty() && (rest.trim().is_empty() || rest.trim().starts_with('#')) =>
+            {
+                name
+            }
+            _ => return Err(invalid_device()),
+        },
+        None => {
+            let name = name.split('#').next().unwrap().trim();
+            if name.contains(char::is_whitespace) {
+                return Err(invalid_device());
+            }
+            name
+        }
+    };
+    Ok((!name.is_empty()).then(|| name.to_string()))
+}
+
+// Parse an `alias <name> = <key or modifier>` statement and add it to `aliases`.
+// An alias can stand for another alias, it is resolved right away so later redefinitions of that
+// alias don't change it.
+fn parse_alias(
+    path: PathBuf,
+    line: &str,
+    line_nr: u32,
+    key_to_evdev_key: &HashMap<&str, evdev::Key>,
+    mod_to_mod_enum: &HashMap<&str, Modifier>,
+    options: &ParseOptions,
+    aliases: &mut HashMap<String, String>,
+) -> Result<(), Error> {
+    let definition = line.strip_prefix(ALIAS_STATEMENT).unwrap().split('#').next().unwrap();
+    let (name, target) = match definition.split_once('=') {
+        Some((name, target)) => (name.trim().to_lowercase(), target.trim().to_lowercase()),
+        None => return Err(Error::InvalidConfig(ParseError::InvalidAlias(path, line_nr))),
+    };
+
+    // Builtin names can't be redefined, that would make configs very confusing to read
+    if name.is_empty()
+        || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        || mod_to_mod_enum.contains_key(name.as_str())
+        || match_keysym(&name, key_to_evdev_key, false).is_some()
+        || [ANY_MODIFIER, IMPORT_STATEMENT, ALIAS_STATEMENT, DEVICE_STATEMENT, IGNORE_STATEMENT]
+            .contains(&name.as_str())
+    {
+        return Err(Error::InvalidConfig(ParseError::InvalidAlias(path, line_nr)));
+    }
+
+    let target = match aliases.get(&target) {
+        Some(aliased) => aliased.clone(),
+        None if mod_to_mod_enum.contains_key(target.as_str())
+            || match_keysym(&target, key_to_evdev_key, options.strict_namespaces).is_some() =>
+        {
+            target
+        }
+        None => return Err(Error::InvalidConfig(ParseError::UndefinedAlias(path, line_nr))),
+    };
+    aliases.insert(name, target);
+    Ok(())
+}
+
+// Split a keybinding line into the keybindings of a chord. A `;` is the semicolon key when it
+// stands where a key is expected (at the start of the line, after `+` or after a `@`/`~` prefix),
+// otherwise it separates two keybindings.
+fn split_chord(line: &str) -> Vec<&str> {
+    let mut segments = Vec::new();
+    let mut start = 0;
+    for (i, c) in line.char_indices() {
+        if c != ';' {
+            continue;
+        }
+        let before = line[start..i].trim_end();
+        if (start == 0 && before.is_empty()) || before.ends_with(['+', '@', '~']) {
+            continue;
+        }
+        segments.push(&line[start..i]);
+        start = i + 1;
+    }
+    segments.push(&line[start..]);
+    segments
+}
+
+fn parse_chord(
+    path: PathBuf,
+    line: &str,
+    line_nr: u32,
+    key_to_evdev_key: &HashMap<&str, evdev::Key>,
+    mod_to_mod_enum: &HashMap<&str, Modifier>,
+    options: &ParseOptions,
+    aliases: &HashMap<String, String>,
+) -> Result<Chord, Error> {
+    let segments = split_chord(line);
+    if segments.len() > 1 && segments.iter().any(|segment| segment.trim().is_empty()) {
+        return Err(Error::InvalidConfig(ParseError::InvalidChord(path, line_nr)));
+    }
+
+    let keybindings = segments
+        .iter()
+        .map(|segment| {
+            parse_keybind(
+                path.clone(),
+                segment,
+                line_nr,
+                key_to_evdev_key,
+                mod_to_mod_enum,
+                options,
+                aliases,
+            )
+        })
+        .collect::<Result<Vec<KeyBinding>, Error>>()?;
+    Ok(Chord(keybindings))
+}
+
+// A modifier written twice, or a sided one next to its generic form like `shift + lshift`, which
+// the generic one already covers
+fn has_duplicate_modifier(modifiers: &[Modifier]) -> bool {
+    modifiers.iter().duplicates().next().is_some()
+        || modifiers.iter().any(|modifier| {
+            modifier.generic() != *modifier && modifiers.contains(&modifier.generic())
+        })
+}
+
 // We need to get the reference to key_to_evdev_key
 // and mod_to_mod enum instead of recreating them
 // after each function call because it's too expensive
@@ -534,17 +1492,105 @@ fn parse_keybind(
     line_nr: u32,
     key_to_evdev_key: &HashMap<&str, evdev::Key>,
     mod_to_mod_enum: &HashMap<&str, Modifier>,
+    options: &ParseOptions,
+    aliases: &HashMap<String, String>,
 ) -> Result<KeyBinding, Error> {
     let line = line.split('#').next().unwrap();
     let tokens: Vec<String> =
         line.split('+').map(|s| s.trim().to_lowercase()).filter(|s| s != "_").collect();
 
+    // A line of only whitespace or `_` placeholders has nothing to bind
+    if tokens.is_empty() || tokens.iter().all(|token| token.trim_start_matches('_').is_empty()) {
+        return Err(Error::InvalidConfig(ParseError::UnknownSymbol(path, line_nr)));
+    }
+
     let mut tokens_new = Vec::new();
     for mut token in tokens {
         while token.trim().starts_with('_') {
             token = token.trim().strip_prefix('_').unwrap().to_string();
         }
-        tokens_new.push(token.trim().to_string());
+        let token = token.trim();
+        // Replace aliases with what they stand for, keeping any `@` or `~` prefix
+        let name = token.trim_start_matches(['@', '~']);
+        match aliases.get(name) {
+            Some(target) => {
+                tokens_new.push(format!("{}{}", &token[..token.len() - name.len()], target))
+            }
+            None => tokens_new.push(token.to_string()),
+        }
+    }
+
+    // An uppercase letter or a shifted symbol like `!` is the key with shift held, the characters
+    // that already mean something else in a keybinding (`@`, `~`, `_`, `+`, `{`, `}`, `#`) are left
+    // out
+    let implied_shift = match tokens_new.last_mut() {
+        Some(token) => {
+            // The last key as written, before lowercasing, without the `_` placeholders
+            let raw = line
+                .split('+')
+                .map(str::trim)
+                .rfind(|raw| *raw != "_")
+                .unwrap()
+                .trim_start_matches('_')
+                .trim()
+                .trim_start_matches(['@', '~']);
+            let prefix_len = token.len() - token.trim_start_matches(['@', '~']).len();
+            let unshifted = match raw {
+                "!" => Some("1"),
+                "$" => Some("4"),
+                "%" => Some("5"),
+                "^" => Some("6"),
+                "&" => Some("7"),
+                "*" => Some("8"),
+                "(" => Some("9"),
+                ")" => Some("0"),
+                "|" => Some("backslash"),
+                ":" => Some(";"),
+                "\"" => Some("'"),
+                "<" => Some(","),
+                ">" => Some("."),
+                "?" => Some("/"),
+                _ => None,
+            };
+            if let Some(unshifted) = unshifted {
+                token.replace_range(prefix_len.., unshifted);
+            }
+            unshifted.is_some() || (raw.len() == 1 && raw.chars().all(|c| c.is_ascii_uppercase()))
+        }
+        None => false,
+    };
+
+    // Nothing between two `+`, a `+` at either end is reported as an unknown symbol below
+    if tokens_new.len() > 2 && tokens_new[1..tokens_new.len() - 1].iter().any(String::is_empty) {
+        return Err(Error::InvalidConfig(ParseError::EmptyToken(path, line_nr)));
+    }
+
+    // `ctrl*2` is a modifier tapped twice, only a lone modifier can be repeated
+    let mut repeat = 1;
+    let repeated = |token: &str| {
+        token
+            .rsplit_once('*')
+            .filter(|(name, _)| mod_to_mod_enum.contains_key(name))
+            .map(|(name, count)| (name.to_string(), count.parse::<u32>().ok()))
+    };
+    if let Some((name, count)) = tokens_new.iter().find_map(|token| repeated(token)) {
+        match count {
+            Some(count) if count >= 2 && tokens_new.len() == 1 => {
+                repeat = count;
+                tokens_new[0] = name;
+            }
+            _ => return Err(Error::InvalidConfig(ParseError::InvalidModifier(path, line_nr))),
+        }
+    }
+
+    // `any` stands in for every combination of modifiers, so it can't be mixed with other ones
+    let match_any_modifier =
+        tokens_new[..tokens_new.len() - 1].iter().any(|token| token == ANY_MODIFIER);
+    if match_any_modifier {
+        if tokens_new.len() != 2 {
+            return Err(Error::InvalidConfig(ParseError::InvalidModifier(path, line_nr)));
+        }
+        tokens_new.remove(0);
     }
 
     let last_token = tokens_new.last().unwrap().trim();
@@ -579,10 +1625,25 @@ fn parse_keybind(
 
     let last_token = strip_at(last_token);
 
+    // A binding of nothing but modifiers fires when the modifiers are tapped
+    if tokens_new.iter().all(|token| mod_to_mod_enum.contains_key(token.as_str())) {
+        if match_any_modifier {
+            return Err(Error::InvalidConfig(ParseError::InvalidModifier(path, line_nr)));
+        }
+        let modifiers: Vec<Modifier> =
+            tokens_new.iter().map(|token| *mod_to_mod_enum.get(token.as_str()).unwrap()).collect();
+        if has_duplicate_modifier(&modifiers) {
+            return Err(Error::InvalidConfig(ParseError::DuplicateModifier(path, line_nr)));
+        }
+        let mut keybinding = KeyBinding::modifier_only(modifiers).normalized();
+        keybinding.repeat = repeat;
+        return Ok(keybinding);
+    }
+
     // Check if each token is valid
     for token in &tokens_new {
         let token = strip_at(token);
-        if key_to_evdev_key.contains_key(token) {
+        if match_keysym(token, key_to_evdev_key, options.strict_namespaces).is_some() {
             // Can't have a keysym that's like a modifier
             if token != last_token {
                 return Err(Error::InvalidConfig(ParseError::InvalidModifier(path, line_nr)));
@@ -592,20 +1653,45 @@ fn parse_keybind(
             if token == last_token {
                 return Err(Error::InvalidConfig(ParseError::InvalidKeysym(path, line_nr)));
             }
+        } else if token == last_token
+            && (is_keycode(token) || token.contains(':') || key_to_evdev_key.contains_key(token))
+        {
+            // Keycodes out of range, unknown names in a namespace and bare names in strict mode
+            return Err(Error::InvalidConfig(ParseError::InvalidKeysym(path, line_nr)));
         } else {
             return Err(Error::InvalidConfig(ParseError::UnknownSymbol(path, line_nr)));
         }
     }
 
     // Translate keypress into evdev key
-    let keysym = key_to_evdev_key.get(last_token).unwrap();
+    let keysym = match_keysym(last_token, key_to_evdev_key, options.strict_namespaces).unwrap();
+    // Only keyboards are grabbed, so a mouse button only fires on a mouse that is a keyboard too
+    if (evdev::Key::BTN_LEFT.code()..=evdev::Key::BTN_TASK.code()).contains(&keysym.code()) {
+        log::warn!(
+            "{:?} at line {} of {:?} is a mouse button, it only fires when pressed on a keyboard",
+            keysym,
+            line_nr,
+            path
+        );
+    }
 
-    let modifiers: Vec<Modifier> = tokens_new[0..(tokens_new.len() - 1)]
+    let mut modifiers: Vec<Modifier> = tokens_new[0..(tokens_new.len() - 1)]
         .iter()
         .map(|token| *mod_to_mod_enum.get(token.as_str()).unwrap())
         .collect();
+    if has_duplicate_modifier(&modifiers) {
+        return Err(Error::InvalidConfig(ParseError::DuplicateModifier(path, line_nr)));
+    }
+    // `shift + Q` already has its shift
+    if implied_shift
+        && !match_any_modifier
+        && !modifiers.iter().any(|modifier| modifier.generic() == Modifier::Shift)
+    {
+        modifiers.push(Modifier::Shift);
+    }
 
-    let mut keybinding = KeyBinding::new(*keysym, modifiers);
+    let mut keybinding = KeyBinding::new(keysym, modifiers).normalized();
+    keybinding.match_any_modifier = match_any_modifier;
     if send {
         keybinding = keybinding.send();
     }
@@ -615,6 +1701,54 @@ fn parse_keybind(
     Ok(keybinding)
 }
 
+// Resolve a keysym to an evdev key. The namespace of a keysym can be spelled out to avoid any
+// ambiguity: `key:a` for named keys, `code:58` for raw keycodes and `btn:left` or `btn:3` for
+// mouse buttons. In strict mode only keysyms with a namespace are accepted.
+fn match_keysym(
+    token: &str,
+    key_to_evdev_key: &HashMap<&str, evdev::Key>,
+    strict: bool,
+) -> Option<evdev::Key> {
+    if let Some(name) = token.strip_prefix("key:") {
+        return key_to_evdev_key.get(name).copied();
+    }
+    if let Some(button) = token.strip_prefix("btn:") {
+        let name = if button.chars().all(|c| c.is_ascii_digit()) {
+            format!("button{}", button)
+        } else {
+            format!("btn_{}", button)
+        };
+        return key_to_evdev_key.get(name.as_str()).copied();
+    }
+    if token.starts_with("code:") {
+        return parse_keycode(token);
+    }
+    if strict {
+        return None;
+    }
+    key_to_evdev_key.get(token).copied().or_else(|| parse_keycode(token))
+}
+
+// Highest key code known to evdev, see KEY_MAX in linux/input-event-codes.h
+const KEY_MAX: u16 = 0x2ff;
+
+// Raw keycodes can be written either as `code:190` or as `0xbe`
+fn is_keycode(token: &str) -> bool {
+    token.starts_with("code:") || token.starts_with("0x")
+}
+
+fn parse_keycode(token: &str) -> Option<evdev::Key> {
+    let code = if let Some(code) = token.strip_prefix("code:") {
+        code.parse::<u16>().ok()?
+    } else {
+        u16::from_str_radix(token.strip_prefix("0x")?, 16).ok()?
+    };
+    if code == 0 || code > KEY_MAX {
+        return None;
+    }
+    Some(evdev::Key::new(code))
+}
+
 pub fn extract_curly_brace(line: &str) -> Vec<String> {
     if !line.contains('{') || !line.contains('}') || !line.is_ascii() {
         return vec![line.to_string()];
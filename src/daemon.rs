@@ -8,7 +8,7 @@ use nix::{
 use signal_hook::consts::signal::*;
 use signal_hook_tokio::Signals;
 use std::{
-    collections::{HashMap, HashSet},
+    collections::HashSet,
     env,
     error::Error,
     fs,
@@ -26,19 +26,30 @@ use tokio_stream::{StreamExt, StreamMap};
 
 mod config;
 mod perms;
+mod plan;
+mod syntax;
+mod tap;
 mod uinput;
 
+#[cfg(test)]
+mod simulate;
 #[cfg(test)]
 mod tests;
 
 struct KeyboardState {
     state_modifiers: HashSet<config::Modifier>,
     state_keysyms: AttributeSet<evdev::Key>,
+    // Matched against the `device` statement of hotkeys
+    device_name: String,
 }
 
 impl KeyboardState {
-    fn new() -> KeyboardState {
-        KeyboardState { state_modifiers: HashSet::new(), state_keysyms: AttributeSet::new() }
+    fn new(device_name: String) -> KeyboardState {
+        KeyboardState {
+            state_modifiers: HashSet::new(),
+            state_keysyms: AttributeSet::new(),
+            device_name,
+        }
     }
 }
 
@@ -54,6 +65,48 @@ async fn main() -> Result<(), Box<dyn Error>> {
     env_logger::init();
     log::trace!("Logger initialized.");
 
+    let config_file_path: PathBuf = if args.is_present("config") {
+        Path::new(args.value_of("config").unwrap()).to_path_buf()
+    } else {
+        fetch_xdg_config_path()
+    };
+
+    let parse_options = config::ParseOptions {
+        strict_namespaces: args.is_present("strict-namespaces"),
+        max_config_size: args.value_of("max-config-size").map(|size| size.parse::<u64>().unwrap()),
+        ..Default::default()
+    };
+
+    if args.is_present("show-includes") {
+        drop_to_invoking_user();
+        match config::format_include_tree(&config_file_path) {
+            Ok(tree) => print!("{}", tree),
+            Err(e) => {
+                log::error!("Config Error: {}", e);
+                exit(1);
+            }
+        }
+        exit(0);
+    }
+
+    #[cfg(feature = "serde")]
+    if args.is_present("dump-config") {
+        drop_to_invoking_user();
+        match config::parse_config_lenient(&config_file_path, &parse_options) {
+            Ok((hotkeys, missing)) => {
+                for path in &missing {
+                    log::warn!("Skipping the include of {:?}, the file doesn't exist", path);
+                }
+                println!("{}", config::hotkeys_to_json(&hotkeys))
+            }
+            Err(e) => {
+                log::error!("Config Error: {}", e);
+                exit(1);
+            }
+        }
+        exit(0);
+    }
+
     let invoking_uid = match env::var("PKEXEC_UID") {
         Ok(uid) => {
             let uid = uid.parse::<u32>().unwrap();
@@ -73,17 +126,14 @@ async fn main() -> Result<(), Box<dyn Error>> {
         // Drop privileges to the invoking user.
         perms::drop_privileges(invoking_uid);
 
-        let config_file_path: PathBuf = if args.is_present("config") {
-            Path::new(args.value_of("config").unwrap()).to_path_buf()
-        } else {
-            fetch_xdg_config_path()
-        };
-
         log::debug!("Using config file path: {:#?}", config_file_path);
 
-        let hotkeys = match config::load(&config_file_path) {
+        let hotkeys = match config::parse_config_with_options(&config_file_path, &parse_options) {
             Err(e) => {
                 log::error!("Config Error: {}", e);
+                for path in config::unresolved_includes(&config_file_path).unwrap_or_default() {
+                    log::error!("The included file {:?} doesn't exist", path);
+                }
                 exit(1);
             }
             Ok(out) => out,
@@ -92,11 +142,19 @@ async fn main() -> Result<(), Box<dyn Error>> {
         for hotkey in &hotkeys {
             log::debug!("hotkey: {:#?}", hotkey);
         }
+        for (first, shadowed) in config::find_conflicts(&hotkeys) {
+            log::warn!(
+                "{:?} is shadowed by {:?}, both are bound to {:?}",
+                shadowed.command,
+                first.command,
+                first.keybinding
+            );
+        }
 
-        hotkeys
+        plan::GrabPlan::from(hotkeys)
     };
 
-    let mut hotkeys = load_config();
+    let mut plan = load_config();
 
     // Escalate back to the root user after reading the config file.
     perms::raise_privileges();
@@ -119,16 +177,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
     }
     log::debug!("{} Keyboard device(s) detected.", keyboard_devices.len());
 
-    let modifiers_map: HashMap<Key, config::Modifier> = HashMap::from([
-        (Key::KEY_LEFTMETA, config::Modifier::Super),
-        (Key::KEY_RIGHTMETA, config::Modifier::Super),
-        (Key::KEY_LEFTALT, config::Modifier::Alt),
-        (Key::KEY_RIGHTALT, config::Modifier::Alt),
-        (Key::KEY_LEFTCTRL, config::Modifier::Control),
-        (Key::KEY_RIGHTCTRL, config::Modifier::Control),
-        (Key::KEY_LEFTSHIFT, config::Modifier::Shift),
-        (Key::KEY_RIGHTSHIFT, config::Modifier::Shift),
-    ]);
+    let modifiers_map = tap::modifiers_map();
 
     let repeat_cooldown_duration: u64 = if args.is_present("cooldown") {
         args.value_of("cooldown").unwrap().parse::<u64>().unwrap()
@@ -136,21 +185,25 @@ async fn main() -> Result<(), Box<dyn Error>> {
         250
     };
 
-    let mut signals = Signals::new(&[
+    let mut signals = Signals::new([
         SIGUSR1, SIGUSR2, SIGHUP, SIGABRT, SIGBUS, SIGCHLD, SIGCONT, SIGINT, SIGPIPE, SIGQUIT,
         SIGSYS, SIGTERM, SIGTRAP, SIGTSTP, SIGVTALRM, SIGXCPU, SIGXFSZ,
     ])?;
 
     let mut execution_is_paused = false;
     let mut last_hotkey: Option<config::Hotkey> = None;
+    let mut modifier_tap = tap::ModifierTap::default();
+    let mut tap_repeat = tap::TapRepeat::default();
+    let mut last_fired: Vec<(config::KeyBinding, Instant)> = Vec::new();
     let mut pending_release: bool = false;
     let mut keyboard_states: Vec<KeyboardState> = Vec::new();
     let mut keyboard_stream_map = StreamMap::new();
 
     for (i, mut device) in keyboard_devices.into_iter().enumerate() {
         let _ = device.grab();
+        let device_name = device.name().unwrap_or_default().to_string();
         keyboard_stream_map.insert(i, device.into_event_stream()?);
-        keyboard_states.push(KeyboardState::new());
+        keyboard_states.push(KeyboardState::new(device_name));
     }
 
     // The initial sleep duration is never read because last_hotkey is initialized to None
@@ -166,7 +219,9 @@ async fn main() -> Result<(), Box<dyn Error>> {
                 if hotkey.keybinding.on_release {
                     continue;
                 }
-                send_command(hotkey.clone(), &socket_file_path);
+                if cooldown_elapsed(&hotkey, &mut last_fired) {
+                    send_command(hotkey.clone(), &socket_file_path);
+                }
                 hotkey_repeat_timer.as_mut().reset(Instant::now() + Duration::from_millis(repeat_cooldown_duration));
             }
 
@@ -187,7 +242,8 @@ async fn main() -> Result<(), Box<dyn Error>> {
                     }
 
                     SIGHUP => {
-                        hotkeys = load_config();
+                        plan = load_config();
+                        last_fired.clear();
                     }
 
                     SIGINT => {
@@ -221,10 +277,12 @@ async fn main() -> Result<(), Box<dyn Error>> {
                 match event.value() {
                     // Key press
                     1 => {
+                        modifier_tap.press(modifiers_map.get(&key).copied());
                         if let Some(modifier) = modifiers_map.get(&key) {
                             keyboard_state.state_modifiers.insert(*modifier);
                         } else {
                             keyboard_state.state_keysyms.insert(key);
+                            tap_repeat.clear();
                         }
                     }
 
@@ -232,19 +290,34 @@ async fn main() -> Result<(), Box<dyn Error>> {
                     0 => {
                         if last_hotkey.is_some() && pending_release {
                             pending_release = false;
-                            send_command(last_hotkey.clone().unwrap(), &socket_file_path);
+                            let hotkey = last_hotkey.clone().unwrap();
+                            if cooldown_elapsed(&hotkey, &mut last_fired) {
+                                send_command(hotkey, &socket_file_path);
+                            }
                             last_hotkey = None;
                         }
                         if let Some(modifier) = modifiers_map.get(&key) {
                             if let Some(hotkey) = &last_hotkey {
-                                if hotkey.modifiers().contains(modifier) {
+                                if hotkey.modifiers().iter().any(|m| m.matches(*modifier)) {
                                     last_hotkey = None;
                                 }
                             }
                             keyboard_state.state_modifiers.remove(modifier);
+
+                            if let Some(tapped) = modifier_tap.release(*modifier) {
+                                tap_repeat.tap(tapped, Instant::now().into_std());
+                                if let Some(hotkey) = plan.tapped_hotkey(&tap_repeat, &keyboard_state.device_name) {
+                                    if hotkey.keybinding.repeat > 1 {
+                                        tap_repeat.clear();
+                                    }
+                                    if !execution_is_paused && cooldown_elapsed(hotkey, &mut last_fired) {
+                                        send_command(hotkey.clone(), &socket_file_path);
+                                    }
+                                }
+                            }
                         } else if keyboard_state.state_keysyms.contains(key) {
                             if let Some(hotkey) = &last_hotkey {
-                                if key == hotkey.keysym() {
+                                if Some(key) == hotkey.keysym() {
                                     last_hotkey = None;
                                 }
                             }
@@ -255,16 +328,8 @@ async fn main() -> Result<(), Box<dyn Error>> {
                     _ => {}
                 }
 
-                let possible_hotkeys: Vec<&config::Hotkey> = hotkeys.iter()
-                    .filter(|hotkey| hotkey.modifiers().len() == keyboard_state.state_modifiers.len())
-                    .collect();
-
-                let event_in_hotkeys = hotkeys.iter().any(|hotkey| {
-                    hotkey.keysym().code() == event.code() &&
-                    keyboard_state.state_modifiers
-                        .iter()
-                        .all(|x| hotkey.modifiers().contains(x)) &&
-                    keyboard_state.state_modifiers.len() == hotkey.modifiers().len()
+                let event_in_hotkeys = plan.hotkeys_for(Key::new(event.code()), &keyboard_state.device_name).any(|hotkey| {
+                    plan::modifiers_match(hotkey, &keyboard_state.state_modifiers)
                     && !hotkey.is_send()
                         });
 
@@ -273,30 +338,30 @@ async fn main() -> Result<(), Box<dyn Error>> {
                     uinput_device.emit(&[event]).unwrap();
                 }
 
-                if execution_is_paused || possible_hotkeys.is_empty() || last_hotkey.is_some() {
+                if execution_is_paused || last_hotkey.is_some() {
                     continue;
                 }
 
                 log::debug!("state_modifiers: {:#?}", keyboard_state.state_modifiers);
                 log::debug!("state_keysyms: {:#?}", keyboard_state.state_keysyms);
-                log::debug!("hotkey: {:#?}", possible_hotkeys);
-
-                for hotkey in possible_hotkeys {
-                    // this should check if state_modifiers and hotkey.modifiers have the same elements
-                    if keyboard_state.state_modifiers.iter().all(|x| hotkey.modifiers().contains(x))
-                        && keyboard_state.state_modifiers.len() == hotkey.modifiers().len()
-                        && keyboard_state.state_keysyms.contains(hotkey.keysym())
-                    {
-                        last_hotkey = Some(hotkey.clone());
-                        if pending_release { break; }
-                        if hotkey.is_on_release() {
-                            pending_release = true;
-                            break;
-                        }
+
+                let hotkey = plan.pressed_hotkey(
+                    &keyboard_state.state_modifiers,
+                    |key| keyboard_state.state_keysyms.contains(key),
+                    &keyboard_state.device_name,
+                );
+                if let Some(hotkey) = hotkey {
+                    log::debug!("hotkey: {:#?}", hotkey);
+                    last_hotkey = Some(hotkey.clone());
+                    if pending_release { continue; }
+                    if hotkey.is_on_release() {
+                        pending_release = true;
+                        continue;
+                    }
+                    if cooldown_elapsed(hotkey, &mut last_fired) {
                         send_command(hotkey.clone(), &socket_file_path);
-                        hotkey_repeat_timer.as_mut().reset(Instant::now() + Duration::from_millis(repeat_cooldown_duration));
-                        break;
                     }
+                    hotkey_repeat_timer.as_mut().reset(Instant::now() + Duration::from_millis(repeat_cooldown_duration));
                 }
             }
         }
@@ -318,10 +383,47 @@ fn send_command(hotkey: config::Hotkey, socket_path: &Path) {
     }
 }
 
+// Check whether a hotkey with a cooldown may fire again, and if so, remember when it fired.
+fn cooldown_elapsed(
+    hotkey: &config::Hotkey,
+    last_fired: &mut Vec<(config::KeyBinding, Instant)>,
+) -> bool {
+    let cooldown = match hotkey.cooldown_ms {
+        Some(cooldown_ms) => Duration::from_millis(cooldown_ms as u64),
+        None => return true,
+    };
+    let now = Instant::now();
+    match last_fired.iter_mut().find(|(keybinding, _)| *keybinding == hotkey.keybinding) {
+        Some((_, fired_at)) => {
+            if now.duration_since(*fired_at) < cooldown {
+                log::debug!("Hotkey is cooling down: {:#?}", hotkey);
+                return false;
+            }
+            *fired_at = now;
+        }
+        None => last_fired.push((hotkey.keybinding.clone(), now)),
+    }
+    true
+}
+
+// The flags that only inspect the config exit before the daemon is set up, but under pkexec
+// they still run as root. Read the config as the invoking user, like the daemon itself does.
+fn drop_to_invoking_user() {
+    if let Ok(uid) = env::var("PKEXEC_UID") {
+        match uid.parse::<u32>() {
+            Ok(uid) => perms::drop_privileges(uid),
+            Err(_) => {
+                log::error!("Invalid PKEXEC_UID: {:?}", uid);
+                exit(1);
+            }
+        }
+    }
+}
+
 pub fn check_input_group() -> Result<(), Box<dyn std::error::Error>> {
     if !Uid::current().is_root() {
         let groups = nix::unistd::getgroups();
-        for (_, groups) in groups.iter().enumerate() {
+        for groups in groups.iter() {
             for group in groups {
                 let group = Group::from_gid(*group);
                 if group.unwrap().unwrap().name == "input" {
@@ -339,7 +441,7 @@ pub fn check_input_group() -> Result<(), Box<dyn std::error::Error>> {
 }
 
 pub fn check_device_is_keyboard(device: &Device) -> bool {
-    if device.supported_keys().map_or(false, |keys| keys.contains(Key::KEY_ENTER)) {
+    if device.supported_keys().is_some_and(|keys| keys.contains(Key::KEY_ENTER)) {
         if device.name() == Some("swhkd virtual output") {
             return false;
         }
@@ -368,7 +470,27 @@ pub fn set_command_line_args() -> Command<'static> {
                 .takes_value(true)
                 .help("Set a custom repeat cooldown duration. Default is 250ms."),
         )
-        .arg(arg!(-d - -debug).required(false).help("Enable debug mode."));
+        .arg(arg!(-d - -debug).required(false).help("Enable debug mode."))
+        .arg(
+            arg!(--"show-includes")
+                .required(false)
+                .help("Print the tree of files included by the config and exit."),
+        )
+        .arg(
+            arg!(--"strict-namespaces")
+                .required(false)
+                .help("Only accept keysyms with a namespace, like key:a or btn:left."),
+        )
+        .arg(
+            arg!(--"max-config-size" <BYTES>)
+                .required(false)
+                .takes_value(true)
+                .help("Set the largest config file that is read. Default is 256KiB."),
+        );
+    #[cfg(feature = "serde")]
+    let app = app.arg(
+        arg!(--"dump-config").required(false).help("Print the parsed hotkeys as JSON and exit."),
+    );
     app
 }
 
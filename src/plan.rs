@@ -0,0 +1,208 @@
+// The compiled form of a config: everything the daemon needs to know about what to grab and how to
+// react to it, computed once when the config is (re)loaded instead of on every key event.
+use crate::config::{Hotkey, KeyBinding, Modifier, Value, DEFAULT_REPEAT_TIMEOUT_MS};
+use crate::tap::TapRepeat;
+use evdev::Key;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct GrabPlan {
+    // The hotkeys in config order, the buckets below index into this
+    pub hotkeys: Vec<Hotkey>,
+    // Every key that ends a keybinding
+    #[cfg_attr(feature = "serde", serde(serialize_with = "serialize::keys"))]
+    pub keys: BTreeSet<Key>,
+    // Every modifier used by a keybinding
+    pub modifiers: BTreeSet<Modifier>,
+    // The hotkeys ending in a key, in config order
+    #[cfg_attr(feature = "serde", serde(serialize_with = "serialize::buckets"))]
+    pub buckets: BTreeMap<Key, Vec<usize>>,
+    // Bindings of modifiers only, they fire when the modifiers are tapped, see `tap::ModifierTap`
+    pub modifier_only: Vec<usize>,
+    // Chords aren't dispatched yet, they are kept out of the buckets so that the daemon never fires
+    // one on its last keybinding alone. The root of the trie ends no chord.
+    pub chords: ChordNode,
+    pub flags: Flags,
+}
+
+// A step of the chord trie, e.g. `super + r` is the child of the root that `super + r ; h` and
+// `super + r ; l` continue from
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ChordNode {
+    // The hotkeys of the chords ending in this step, more than one if scoped to different devices
+    pub hotkeys: Vec<usize>,
+    #[cfg_attr(feature = "serde", serde(serialize_with = "serialize::steps"))]
+    pub next: HashMap<KeyBinding, ChordNode>,
+}
+
+impl ChordNode {
+    fn insert(&mut self, steps: &[KeyBinding], index: usize) {
+        match steps.split_first() {
+            Some((step, rest)) => self.next.entry(step.clone()).or_default().insert(rest, index),
+            None => self.hotkeys.push(index),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Flags {
+    // Some keybinding fires on key release (`@`)
+    pub on_release: bool,
+    // Some keybinding lets its key through to other applications (`~`)
+    pub send: bool,
+    // Some keybinding matches any modifiers (`any`)
+    pub match_any_modifier: bool,
+}
+
+impl From<Vec<Hotkey>> for GrabPlan {
+    fn from(hotkeys: Vec<Hotkey>) -> Self {
+        let mut plan = GrabPlan::default();
+        for (index, hotkey) in hotkeys.iter().enumerate() {
+            if let Some(chord) = &hotkey.chord {
+                plan.chords.insert(&chord.0, index);
+                continue;
+            }
+            plan.modifiers.extend(hotkey.modifiers());
+            match hotkey.keysym() {
+                Some(keysym) => {
+                    plan.keys.insert(keysym);
+                    plan.buckets.entry(keysym).or_default().push(index);
+                }
+                None => plan.modifier_only.push(index),
+            }
+            plan.flags.on_release |= hotkey.is_on_release();
+            plan.flags.send |= hotkey.is_send();
+            plan.flags.match_any_modifier |= hotkey.is_match_any_modifier();
+        }
+        plan.hotkeys = hotkeys;
+        plan
+    }
+}
+
+impl GrabPlan {
+    // The hotkeys ending in `key` that fire on `device`
+    pub fn hotkeys_for<'a>(
+        &'a self,
+        key: Key,
+        device: &'a str,
+    ) -> impl Iterator<Item = &'a Hotkey> {
+        self.buckets
+            .get(&key)
+            .into_iter()
+            .flatten()
+            .map(|&index| &self.hotkeys[index])
+            .filter(move |hotkey| hotkey.fires_on(device))
+    }
+
+    pub fn modifier_only_hotkeys<'a>(
+        &'a self,
+        device: &'a str,
+    ) -> impl Iterator<Item = &'a Hotkey> {
+        self.modifier_only
+            .iter()
+            .map(|&index| &self.hotkeys[index])
+            .filter(move |hotkey| hotkey.fires_on(device))
+    }
+
+    // The hotkeys the daemon can fire for events from `device`, in config order
+    pub fn dispatchable<'a>(&'a self, device: &'a str) -> impl Iterator<Item = &'a Hotkey> {
+        self.hotkeys
+            .iter()
+            .filter(|hotkey| hotkey.chord.is_none())
+            .filter(move |hotkey| hotkey.fires_on(device))
+    }
+
+    // The hotkey fired by pressing a key while `modifiers` are held, `is_held` tells which keys are
+    // down. A binding that names the held modifiers, like `super + m`, wins over `any + m`.
+    pub fn pressed_hotkey<'a>(
+        &'a self,
+        modifiers: &HashSet<Modifier>,
+        is_held: impl Fn(Key) -> bool,
+        device: &'a str,
+    ) -> Option<&'a Hotkey> {
+        self.dispatchable(device)
+            .filter(|hotkey| {
+                modifiers_match(hotkey, modifiers) && hotkey.keysym().is_some_and(&is_held)
+            })
+            .min_by_key(|hotkey| hotkey.is_match_any_modifier())
+    }
+
+    // The modifier-only hotkey fired by the last tap of `taps`, see `tap::ModifierTap`. When both
+    // `ctrl` and `ctrl*2` are bound, the first tap fires `ctrl` and the second one `ctrl*2`.
+    pub fn tapped_hotkey<'a>(&'a self, taps: &TapRepeat, device: &'a str) -> Option<&'a Hotkey> {
+        self.modifier_only_hotkeys(device)
+            .filter(|hotkey| modifiers_match(hotkey, taps.modifiers()))
+            .filter(|hotkey| {
+                let timeout_ms = hotkey.timeout_ms.unwrap_or(DEFAULT_REPEAT_TIMEOUT_MS);
+                taps.repeated(hotkey.keybinding.repeat, Duration::from_millis(timeout_ms.into()))
+            })
+            .min_by_key(|hotkey| Reverse(hotkey.keybinding.repeat))
+    }
+}
+
+// Check whether the held modifiers are exactly the ones of a hotkey
+pub fn modifiers_match(hotkey: &Hotkey, modifiers: &HashSet<Modifier>) -> bool {
+    let held: Vec<Modifier> = modifiers.iter().copied().collect();
+    hotkey.keybinding.matches_modifiers(&held)
+}
+
+// Like `config::serialize_key`, for the collections of keys
+#[cfg(feature = "serde")]
+mod serialize {
+    use super::ChordNode;
+    use crate::config::KeyBinding;
+    use evdev::Key;
+    use serde::ser::{SerializeMap, SerializeSeq, Serializer};
+    use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+    pub fn keys<S: Serializer>(keys: &BTreeSet<Key>, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(keys.len()))?;
+        for key in keys {
+            seq.serialize_element(&format!("{:?}", key))?;
+        }
+        seq.end()
+    }
+
+    pub fn buckets<S: Serializer>(
+        buckets: &BTreeMap<Key, Vec<usize>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(buckets.len()))?;
+        for (key, hotkeys) in buckets {
+            map.serialize_entry(&format!("{:?}", key), hotkeys)?;
+        }
+        map.end()
+    }
+
+    #[derive(serde::Serialize)]
+    struct Step<'a> {
+        keybinding: &'a KeyBinding,
+        #[serde(flatten)]
+        node: &'a ChordNode,
+    }
+
+    // The first hotkey of a step or the steps after it, to write the steps out in config order
+    fn first_hotkey(node: &ChordNode) -> Option<usize> {
+        node.hotkeys.iter().copied().chain(node.next.values().filter_map(first_hotkey)).min()
+    }
+
+    // The steps of the chord trie as a list, a map would need the keybindings as strings
+    pub fn steps<S: Serializer>(
+        steps: &HashMap<KeyBinding, ChordNode>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let mut steps: Vec<Step> =
+            steps.iter().map(|(keybinding, node)| Step { keybinding, node }).collect();
+        steps.sort_by_key(|step| first_hotkey(step.node));
+        let mut seq = serializer.serialize_seq(Some(steps.len()))?;
+        for step in steps {
+            seq.serialize_element(&step)?;
+        }
+        seq.end()
+    }
+}
@@ -0,0 +1,87 @@
+// Replays key presses and releases against a compiled config the way the daemon reacts to events
+// from a keyboard, so that configs can be tested without one.
+// Only what decides which hotkey fires is simulated, key repeats and cooldowns are left out. Time
+// only passes with `wait`.
+use crate::config::{Hotkey, Modifier, Value};
+use crate::plan::GrabPlan;
+use crate::tap::{self, ModifierTap, TapRepeat};
+use evdev::Key;
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+pub struct Simulation<'a> {
+    plan: &'a GrabPlan,
+    // The name of the simulated keyboard, for hotkeys scoped with `device`
+    device: &'a str,
+    modifiers_map: HashMap<Key, Modifier>,
+    tap: ModifierTap,
+    tap_repeat: TapRepeat,
+    now: Instant,
+    modifiers: HashSet<Modifier>,
+    keys: HashSet<Key>,
+}
+
+impl<'a> Simulation<'a> {
+    pub fn new(plan: &'a GrabPlan, device: &'a str) -> Self {
+        Simulation {
+            plan,
+            device,
+            modifiers_map: tap::modifiers_map(),
+            tap: ModifierTap::default(),
+            tap_repeat: TapRepeat::default(),
+            now: Instant::now(),
+            modifiers: HashSet::new(),
+            keys: HashSet::new(),
+        }
+    }
+
+    // Press a key, returns the hotkey that fires on the press
+    pub fn press(&mut self, key: Key) -> Option<&'a Hotkey> {
+        let modifier = self.modifiers_map.get(&key).copied();
+        self.tap.press(modifier);
+        match modifier {
+            Some(modifier) => self.modifiers.insert(modifier),
+            None => {
+                self.tap_repeat.clear();
+                self.keys.insert(key)
+            }
+        };
+        self.held_hotkey().filter(|hotkey| !hotkey.is_on_release())
+    }
+
+    // Release a key, returns the hotkey that fires on the release: a binding with `@`, or one of
+    // modifiers only once they were tapped
+    pub fn release(&mut self, key: Key) -> Option<&'a Hotkey> {
+        match self.modifiers_map.get(&key).copied() {
+            Some(modifier) => {
+                self.modifiers.remove(&modifier);
+                let tapped = self.tap.release(modifier)?;
+                self.tap_repeat.tap(tapped, self.now);
+                let hotkey = self.plan.tapped_hotkey(&self.tap_repeat, self.device)?;
+                if hotkey.keybinding.repeat > 1 {
+                    self.tap_repeat.clear();
+                }
+                Some(hotkey)
+            }
+            None => {
+                let hotkey = self.held_hotkey().filter(|hotkey| hotkey.is_on_release());
+                self.keys.remove(&key);
+                hotkey
+            }
+        }
+    }
+
+    // Press and release a key
+    pub fn tap(&mut self, key: Key) -> Vec<&'a Hotkey> {
+        self.press(key).into_iter().chain(self.release(key)).collect()
+    }
+
+    // Let time pass between two key events
+    pub fn wait(&mut self, duration: Duration) {
+        self.now += duration;
+    }
+
+    fn held_hotkey(&self) -> Option<&'a Hotkey> {
+        self.plan.pressed_hotkey(&self.modifiers, |key| self.keys.contains(&key), self.device)
+    }
+}
@@ -0,0 +1,229 @@
+// A lossless syntax tree of a config file.
+// Unlike `config::parse_contents`, nothing is thrown away here: every line keeps its exact text
+// (whitespace, comments and blank lines included) so that tools like a formatter can edit a config
+// and write it back without touching anything they did not mean to change.
+//
+// The daemon does not use this (yet), it is the base for non-destructive config tooling.
+
+use crate::config::{ALIAS_STATEMENT, DEVICE_STATEMENT, IGNORE_STATEMENT, IMPORT_STATEMENT};
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxTree {
+    pub items: Vec<Item>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Item {
+    // Blank lines, comments and command lines that don't belong to a keybinding
+    Trivia(Line),
+    // Statements such as `include`, `alias`, `device` and `ignore`
+    Statement(Line),
+    Binding(Binding),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Binding {
+    // The keybinding, more than one line if continued with a backslash
+    pub keys: Vec<Line>,
+    // Blank lines and comments between the keybinding and its command
+    pub trivia: Vec<Line>,
+    pub command: Vec<Line>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Line {
+    // The raw text of the line without its line feed
+    pub text: String,
+    // Whether the line was terminated by a line feed, only the last line of a file may not be
+    pub terminated: bool,
+}
+
+impl Line {
+    fn new(raw: &str) -> Self {
+        match raw.strip_suffix('\n') {
+            Some(text) => Line { text: text.to_string(), terminated: true },
+            None => Line { text: raw.to_string(), terminated: false },
+        }
+    }
+
+    fn is_trivia(&self) -> bool {
+        self.text.trim().is_empty() || self.text.trim().starts_with('#')
+    }
+
+    fn is_statement(&self) -> bool {
+        let keyword = self.text.split(' ').next().unwrap();
+        [IMPORT_STATEMENT, ALIAS_STATEMENT, DEVICE_STATEMENT, IGNORE_STATEMENT].contains(&keyword)
+    }
+
+    fn is_command(&self) -> bool {
+        self.text.starts_with(' ') || self.text.starts_with('\t')
+    }
+
+    fn is_continued(&self) -> bool {
+        self.text.trim_end().ends_with('\\')
+    }
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)?;
+        if self.terminated {
+            f.write_str("\n")?;
+        }
+        Ok(())
+    }
+}
+
+#[allow(dead_code)]
+impl Binding {
+    // Replace every occurrence of the `from` keysym or modifier in the keybinding with `to`,
+    // keeping the whitespace around it. Tokens are separated by `+`, the `;` between the steps of
+    // a chord and the `{`, `,` and `}` of a range. Returns whether anything was replaced.
+    pub fn rename_keysym(&mut self, from: &str, to: &str) -> bool {
+        const SEPARATORS: [char; 5] = ['+', ';', '{', ',', '}'];
+        let mut renamed = false;
+        for line in &mut self.keys {
+            let mut text = String::new();
+            for token in line.text.split_inclusive(SEPARATORS) {
+                let (token, separator) = match token.strip_suffix(SEPARATORS) {
+                    Some(stripped) => token.split_at(stripped.len()),
+                    None => (token, ""),
+                };
+                if token.trim().eq_ignore_ascii_case(from) {
+                    renamed = true;
+                    let start = token.len() - token.trim_start().len();
+                    let end = token.trim_end().len();
+                    text.push_str(&format!("{}{}{}", &token[..start], to, &token[end..]));
+                } else {
+                    text.push_str(token);
+                }
+                text.push_str(separator);
+            }
+            line.text = text;
+        }
+        renamed
+    }
+}
+
+impl fmt::Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for line in self.keys.iter().chain(&self.trivia).chain(&self.command) {
+            line.fmt(f)?;
+        }
+        Ok(())
+    }
+}
+
+// The entry points for config tooling, unused by the daemon
+#[allow(dead_code)]
+impl SyntaxTree {
+    pub fn parse(contents: &str) -> Self {
+        let mut items = Vec::new();
+        let mut lines = contents.split_inclusive('\n').map(Line::new).peekable();
+
+        while let Some(line) = lines.next() {
+            if line.is_trivia() {
+                items.push(Item::Trivia(line));
+                continue;
+            }
+            if line.is_statement() {
+                items.push(Item::Statement(line));
+                continue;
+            }
+            if line.is_command() {
+                // A command without a keybinding is ignored by the parser
+                items.push(Item::Trivia(line));
+                continue;
+            }
+
+            let mut keys = vec![line];
+            while keys.last().unwrap().is_continued() {
+                match lines.peek() {
+                    Some(next) if !next.is_trivia() && !next.is_command() => {
+                        keys.push(lines.next().unwrap())
+                    }
+                    _ => break,
+                }
+            }
+
+            // Blank lines and comments before the command belong to the binding, the parser skips
+            // them. Without a command after them they are left to the items that follow.
+            let mut trivia = Vec::new();
+            while lines.peek().is_some_and(Line::is_trivia) {
+                trivia.push(lines.next().unwrap());
+            }
+            let mut command = Vec::new();
+            while let Some(next) = lines.peek() {
+                if next.is_command() && !next.is_trivia() {
+                    command.push(lines.next().unwrap());
+                } else {
+                    break;
+                }
+            }
+
+            if command.is_empty() {
+                items.push(Item::Binding(Binding { keys, trivia: Vec::new(), command }));
+                items.extend(trivia.into_iter().map(Item::Trivia));
+            } else {
+                items.push(Item::Binding(Binding { keys, trivia, command }));
+            }
+        }
+
+        SyntaxTree { items }
+    }
+
+    pub fn bindings(&self) -> impl Iterator<Item = &Binding> {
+        self.items.iter().filter_map(|item| match item {
+            Item::Binding(binding) => Some(binding),
+            _ => None,
+        })
+    }
+
+    pub fn bindings_mut(&mut self) -> impl Iterator<Item = &mut Binding> {
+        self.items.iter_mut().filter_map(|item| match item {
+            Item::Binding(binding) => Some(binding),
+            _ => None,
+        })
+    }
+
+    // Swap the places of the a-th and b-th binding, leaving everything around them untouched.
+    // Returns None without changing anything if there is no a-th or b-th binding.
+    pub fn swap_bindings(&mut self, a: usize, b: usize) -> Option<()> {
+        let positions: Vec<usize> = self
+            .items
+            .iter()
+            .enumerate()
+            .filter(|(_, item)| matches!(item, Item::Binding(_)))
+            .map(|(i, _)| i)
+            .collect();
+        let (a, b) = (*positions.get(a)?, *positions.get(b)?);
+        self.items.swap(a, b);
+
+        // Whether a line ends with a line feed belongs to its place in the file, not to the line
+        if let (Item::Binding(first), Item::Binding(second)) = (&self.items[a], &self.items[b]) {
+            let first_terminated = first.command.last().or(first.keys.last()).unwrap().terminated;
+            let second_terminated =
+                second.command.last().or(second.keys.last()).unwrap().terminated;
+            for (position, terminated) in [(a, second_terminated), (b, first_terminated)] {
+                if let Item::Binding(binding) = &mut self.items[position] {
+                    let last = binding.command.last_mut().or(binding.keys.last_mut()).unwrap();
+                    last.terminated = terminated;
+                }
+            }
+        }
+        Some(())
+    }
+}
+
+impl fmt::Display for SyntaxTree {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for item in &self.items {
+            match item {
+                Item::Trivia(line) | Item::Statement(line) => line.fmt(f)?,
+                Item::Binding(binding) => binding.fmt(f)?,
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,96 @@
+// Tells a tap of lone modifiers apart from modifiers held for a combo.
+// Bindings made of modifiers only (`super`) fire once every modifier pressed since the last tap has
+// been released again, unless another key was pressed in the meantime (`super + a`).
+use crate::config::Modifier;
+use evdev::Key;
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+// The modifier each modifier key stands for
+pub fn modifiers_map() -> HashMap<Key, Modifier> {
+    HashMap::from([
+        (Key::KEY_LEFTMETA, Modifier::Super),
+        (Key::KEY_RIGHTMETA, Modifier::Super),
+        (Key::KEY_LEFTALT, Modifier::LeftAlt),
+        (Key::KEY_RIGHTALT, Modifier::RightAlt),
+        (Key::KEY_LEFTCTRL, Modifier::LeftControl),
+        (Key::KEY_RIGHTCTRL, Modifier::RightControl),
+        (Key::KEY_LEFTSHIFT, Modifier::LeftShift),
+        (Key::KEY_RIGHTSHIFT, Modifier::RightShift),
+    ])
+}
+
+#[derive(Debug, Default)]
+pub struct ModifierTap {
+    held: HashSet<Modifier>,
+    tapped: HashSet<Modifier>,
+    interrupted: bool,
+}
+
+impl ModifierTap {
+    // A key was pressed, `None` for a key that isn't a modifier
+    pub fn press(&mut self, modifier: Option<Modifier>) {
+        match modifier {
+            Some(modifier) => {
+                self.held.insert(modifier);
+                self.tapped.insert(modifier);
+            }
+            // Typing without a modifier held doesn't interrupt the next tap
+            None => self.interrupted |= !self.held.is_empty(),
+        }
+    }
+
+    // A modifier was released, returns the tapped modifiers once all of them are released
+    pub fn release(&mut self, modifier: Modifier) -> Option<HashSet<Modifier>> {
+        self.held.remove(&modifier);
+        if !self.held.is_empty() {
+            return None;
+        }
+
+        let tapped = std::mem::take(&mut self.tapped);
+        if std::mem::take(&mut self.interrupted) || tapped.is_empty() {
+            return None;
+        }
+        Some(tapped)
+    }
+}
+
+// The taps of the same modifiers in a row, for bindings like `ctrl*2`
+#[derive(Debug, Default)]
+pub struct TapRepeat {
+    modifiers: HashSet<Modifier>,
+    times: Vec<Instant>,
+}
+
+impl TapRepeat {
+    // Record a tap, a tap of other modifiers starts a new row. Either side of a modifier
+    // continues the row, so `lctrl` then `rctrl` counts for `ctrl*2`
+    pub fn tap(&mut self, modifiers: HashSet<Modifier>, now: Instant) {
+        let generic = |set: &HashSet<Modifier>| -> HashSet<Modifier> {
+            set.iter().map(|modifier| modifier.generic()).collect()
+        };
+        if generic(&modifiers) != generic(&self.modifiers) {
+            self.modifiers = modifiers;
+            self.times.clear();
+        }
+        self.times.push(now);
+    }
+
+    // Another key was pressed or the row fired a binding, the next tap starts a new row
+    pub fn clear(&mut self) {
+        self.modifiers.clear();
+        self.times.clear();
+    }
+
+    pub fn modifiers(&self) -> &HashSet<Modifier> {
+        &self.modifiers
+    }
+
+    // Whether the last `count` taps all happened within `window`
+    pub fn repeated(&self, count: u32, window: Duration) -> bool {
+        let count = count as usize;
+        count > 0
+            && self.times.len() >= count
+            && self.times[self.times.len() - 1] - self.times[self.times.len() - count] <= window
+    }
+}
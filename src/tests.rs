@@ -1,11 +1,21 @@
 mod test_config {
     use crate::config::{
-        extract_curly_brace, load, load_file_contents, parse_contents, Error, Hotkey, Modifier,
-        ParseError, Prefix,
+        extract_curly_brace, find_conflicts, find_duplicate_commands, format_include_tree,
+        home_dir, join_lines, load_file_contents, load_file_contents_with_limit, normalize_command,
+        parse_config, parse_config_lenient, parse_config_with_options, parse_contents,
+        parse_contents_with_options, unresolved_includes, Chord, Config, Error, Hotkey, KeyBinding,
+        Modifier, ParseError, ParseOptions, Prefix, Route, MAX_CONFIG_SIZE,
     };
+    use std::collections::hash_map::DefaultHasher;
+    use std::collections::HashMap;
+    use std::env;
     use std::fs;
+    use std::hash::{Hash, Hasher};
     use std::io::Write;
-    use std::{fs::File, path::PathBuf};
+    use std::{
+        fs::File,
+        path::{Path, PathBuf},
+    };
 
     // Implement a struct for a path used in tests
     // so that the test file will be automatically removed
@@ -28,7 +38,9 @@ mod test_config {
 
     impl Drop for TestPath {
         fn drop(self: &mut TestPath) {
-            if self.path.exists() {
+            if self.path.is_dir() {
+                fs::remove_dir_all(self.path()).unwrap();
+            } else if self.path.exists() {
                 fs::remove_file(self.path()).unwrap();
             }
         }
@@ -40,11 +52,10 @@ mod test_config {
 
         let mut expected_hotkeys_mut = expected_hotkeys;
 
-        if result.is_err() {
-            panic!("Expected Ok config, found Err {:?}", result.unwrap_err());
-        }
-
-        let actual_hotkeys = result.unwrap();
+        let actual_hotkeys = match result {
+            Ok(hotkeys) => hotkeys,
+            Err(e) => panic!("Expected Ok config, found Err {:?}", e),
+        };
 
         assert_eq!(actual_hotkeys.len(), expected_hotkeys_mut.len());
 
@@ -133,6 +144,75 @@ q
         Ok(())
     }
 
+    #[test]
+    fn test_file_size_limit() -> std::io::Result<()> {
+        let setup = TestPath::new("/tmp/swhkd-test-file-size");
+        let mut f = File::create(setup.path())?;
+        f.write_all(b"super + a\n    st\n")?;
+
+        assert_eq!(
+            load_file_contents_with_limit(&setup.path(), 17).unwrap(),
+            "super + a\n    st\n"
+        );
+        match load_file_contents_with_limit(&setup.path(), 16) {
+            Err(Error::ConfigTooLarge(path, 16)) => assert_eq!(path, setup.path()),
+            result => panic!("Expected a config too large error, found {:?}", result),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_binary_file() -> std::io::Result<()> {
+        let setup = TestPath::new("/tmp/swhkd-test-binary-file");
+        let mut f = File::create(setup.path())?;
+        f.write_all(&[0xff; 32])?;
+
+        match load_file_contents_with_limit(&setup.path(), 16) {
+            Err(Error::ConfigTooLarge(path, 16)) => assert_eq!(path, setup.path()),
+            result => panic!("Expected a config too large error, found {:?}", result),
+        }
+        match load_file_contents_with_limit(&setup.path(), 32) {
+            Err(Error::InvalidUtf8(path)) => assert_eq!(path, setup.path()),
+            result => panic!("Expected an invalid UTF-8 error, found {:?}", result),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_config_size_option() -> std::io::Result<()> {
+        let setup = TestPath::new("/tmp/swhkd-test-max-config-size");
+        fs::create_dir_all(setup.path())?;
+        let mut f = File::create(setup.path().join("swhkdrc"))?;
+        f.write_all(b"include /tmp/swhkd-test-max-config-size/large.swhkd\n")?;
+        let mut f = File::create(setup.path().join("large.swhkd"))?;
+        f.write_all(format!("#{}\nsuper + a\n    st\n", "-".repeat(60)).as_bytes())?;
+
+        let options = ParseOptions { max_config_size: Some(60), ..Default::default() };
+        match parse_config_with_options(&setup.path().join("swhkdrc"), &options) {
+            Err(Error::ConfigTooLarge(path, 60)) => {
+                assert_eq!(path, setup.path().join("large.swhkd"))
+            }
+            result => panic!("Expected a config too large error, found {:?}", result),
+        }
+        assert!(parse_config(&setup.path().join("swhkdrc")).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_include_dev_zero() -> std::io::Result<()> {
+        let setup = TestPath::new("/tmp/swhkd-test-dev-zero");
+        let mut f = File::create(setup.path())?;
+        f.write_all(b"include /dev/zero\nsuper + a\n    st\n")?;
+
+        match parse_config(&setup.path()) {
+            Err(Error::ConfigTooLarge(path, MAX_CONFIG_SIZE)) => {
+                assert_eq!(path, Path::new("/dev/zero"))
+            }
+            result => panic!("Expected a config too large error, found {:?}", result),
+        }
+        Ok(())
+    }
+
     #[test]
     fn test_load_multiple_config() -> std::io::Result<()> {
         let setup = TestPath::new("/tmp/swhkd-test-file2");
@@ -152,7 +232,7 @@ super + c
     hello",
         )?;
 
-        let hotkeys = load(&setup.path());
+        let hotkeys = parse_config(&setup.path());
         assert_eq!(
             hotkeys.unwrap(),
             vec!(
@@ -182,7 +262,220 @@ super + c
     hello",
         )?;
 
-        let hotkeys = load(&setup.path());
+        let hotkeys = parse_config(&setup.path());
+        assert_eq!(
+            hotkeys.unwrap(),
+            vec!(
+                Hotkey::new(evdev::Key::KEY_B, vec![Modifier::Super], String::from("firefox")),
+                Hotkey::new(evdev::Key::KEY_C, vec![Modifier::Super], String::from("hello"))
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_config_fixture() -> std::io::Result<()> {
+        let setup = TestPath::new("/tmp/swhkd-test-fixture1");
+        let mut f = File::create(setup.path())?;
+        f.write_all(
+            b"
+# Fixture config
+include /tmp/swhkd-test-fixture2
+
+super + Return
+    alacritty
+
+super + @Escape
+    pkill -USR1 swhkd
+
+super + ~{1,2}
+    bspc desktop -f {1,2}
+
+ctrl + shift + ~@q
+    notify-send \\
+    bye
+",
+        )?;
+
+        let setup2 = TestPath::new("/tmp/swhkd-test-fixture2");
+        let mut f2 = File::create(setup2.path())?;
+        f2.write_all(
+            b"
+# Shadowed by the same keybinding in the root config
+super + Return
+    kitty
+
+alt + f
+    firefox
+",
+        )?;
+
+        let hotkeys = parse_config(&setup.path()).unwrap();
+        assert_eq!(
+            hotkeys,
+            vec![
+                Hotkey::new(
+                    evdev::Key::KEY_ENTER,
+                    vec![Modifier::Super],
+                    String::from("alacritty")
+                ),
+                Hotkey::new(
+                    evdev::Key::KEY_ESC,
+                    vec![Modifier::Super],
+                    String::from("pkill -USR1 swhkd")
+                )
+                .on_release(),
+                Hotkey::new(
+                    evdev::Key::KEY_1,
+                    vec![Modifier::Super],
+                    String::from("bspc desktop -f 1")
+                )
+                .send(),
+                Hotkey::new(
+                    evdev::Key::KEY_2,
+                    vec![Modifier::Super],
+                    String::from("bspc desktop -f 2")
+                )
+                .send(),
+                Hotkey::new(
+                    evdev::Key::KEY_Q,
+                    vec![Modifier::Control, Modifier::Shift],
+                    String::from("notify-send bye")
+                )
+                .send()
+                .on_release(),
+                Hotkey::new(evdev::Key::KEY_ENTER, vec![Modifier::Super], String::from("kitty")),
+                Hotkey::new(evdev::Key::KEY_F, vec![Modifier::Alt], String::from("firefox")),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_config_missing_command() -> std::io::Result<()> {
+        let setup = TestPath::new("/tmp/swhkd-test-fixture3");
+        let mut f = File::create(setup.path())?;
+        f.write_all(
+            b"
+super + Return
+    alacritty
+super + b
+",
+        )?;
+
+        match parse_config(&setup.path()) {
+            Err(Error::InvalidConfig(ParseError::MissingCommand(path, 4))) => {
+                assert_eq!(path, setup.path())
+            }
+            result => panic!("Expected a missing command error, found {:?}", result),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_config_lenient() -> std::io::Result<()> {
+        let setup = TestPath::new("/tmp/swhkd-test-unresolved1");
+        let mut f = File::create(setup.path())?;
+        f.write_all(
+            b"
+include /tmp/swhkd-test-unresolved2
+include /tmp/swhkd-test-unresolved-missing1
+super + b
+   firefox",
+        )?;
+
+        let setup2 = TestPath::new("/tmp/swhkd-test-unresolved2");
+        let mut f2 = File::create(setup2.path())?;
+        f2.write_all(
+            b"
+include /tmp/swhkd-test-unresolved1
+include /tmp/swhkd-test-unresolved-missing2
+super + c
+    hello",
+        )?;
+
+        assert!(matches!(parse_config(&setup.path()), Err(Error::ConfigNotFound)));
+
+        let mut unresolved = unresolved_includes(&setup.path()).unwrap();
+        unresolved.sort();
+        assert_eq!(
+            unresolved,
+            vec![
+                PathBuf::from("/tmp/swhkd-test-unresolved-missing1"),
+                PathBuf::from("/tmp/swhkd-test-unresolved-missing2")
+            ]
+        );
+
+        let (hotkeys, mut unresolved) =
+            parse_config_lenient(&setup.path(), &ParseOptions::default()).unwrap();
+        unresolved.sort();
+        assert_eq!(
+            unresolved,
+            vec![
+                PathBuf::from("/tmp/swhkd-test-unresolved-missing1"),
+                PathBuf::from("/tmp/swhkd-test-unresolved-missing2")
+            ]
+        );
+        assert_eq!(
+            hotkeys,
+            vec![
+                Hotkey::new(evdev::Key::KEY_B, vec![Modifier::Super], String::from("firefox")),
+                Hotkey::new(evdev::Key::KEY_C, vec![Modifier::Super], String::from("hello"))
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_home_dir_of_pkexec_user() {
+        let uid = nix::unistd::getuid();
+        let user = nix::unistd::User::from_uid(uid).unwrap().unwrap();
+
+        assert_eq!(home_dir(Some(&uid.to_string())), Some(user.dir.display().to_string()));
+        assert_eq!(home_dir(Some("root")), None);
+        assert_eq!(home_dir(None), env::var("HOME").ok());
+    }
+
+    #[test]
+    fn test_import_path_expansion() -> std::io::Result<()> {
+        env::set_var("SWHKD_TEST_IMPORT_DIR", "/tmp");
+        let contents = "
+include $SWHKD_TEST_IMPORT_DIR/swhkd-test-env-file2
+include ${SWHKD_TEST_IMPORT_DIR}/swhkd/local.swhkd
+include ~/.config/swhkd/extra.swhkd
+include /tmp/cost$
+super + b
+   firefox";
+
+        let imports = Config::get_imports(Path::new("swhkdrc"), contents).unwrap();
+        assert_eq!(
+            imports,
+            vec![
+                PathBuf::from("/tmp/swhkd-test-env-file2"),
+                PathBuf::from("/tmp/swhkd/local.swhkd"),
+                Path::new(&env::var("HOME").unwrap()).join(".config/swhkd/extra.swhkd"),
+                PathBuf::from("/tmp/cost$"),
+            ]
+        );
+
+        let setup = TestPath::new("/tmp/swhkd-test-env-file1");
+        let mut f = File::create(setup.path())?;
+        f.write_all(
+            b"
+include $SWHKD_TEST_IMPORT_DIR/swhkd-test-env-file2
+super + b
+   firefox",
+        )?;
+
+        let setup2 = TestPath::new("/tmp/swhkd-test-env-file2");
+        let mut f2 = File::create(setup2.path())?;
+        f2.write_all(
+            b"
+super + c
+    hello",
+        )?;
+
+        let hotkeys = parse_config(&setup.path());
         assert_eq!(
             hotkeys.unwrap(),
             vec!(
@@ -193,6 +486,116 @@ super + c
         Ok(())
     }
 
+    #[test]
+    fn test_import_path_undefined_variable() {
+        let contents = "
+super + b
+   firefox
+include $SWHKD_TEST_UNDEFINED_VARIABLE/swhkdrc";
+
+        match Config::get_imports(Path::new("swhkdrc"), contents) {
+            Err(Error::InvalidConfig(parse_err)) => {
+                assert_eq!(parse_err, ParseError::UndefinedVariable(PathBuf::from("swhkdrc"), 4))
+            }
+            result => panic!("Expected an undefined variable error, found {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_format_include_tree() -> std::io::Result<()> {
+        let setup = TestPath::new("/tmp/swhkd-test-tree1");
+        let mut f = File::create(setup.path())?;
+        f.write_all(
+            b"
+include /tmp/swhkd-test-tree2
+include /tmp/swhkd-test-tree3
+include /tmp/swhkd-test-tree-missing
+super + b
+   firefox",
+        )?;
+
+        let setup2 = TestPath::new("/tmp/swhkd-test-tree2");
+        let mut f2 = File::create(setup2.path())?;
+        f2.write_all(
+            b"
+include /tmp/swhkd-test-tree3
+include /tmp/swhkd-test-tree1
+super + c
+    hello",
+        )?;
+
+        let setup3 = TestPath::new("/tmp/swhkd-test-tree3");
+        let mut f3 = File::create(setup3.path())?;
+        f3.write_all(
+            b"
+super + d
+    hello",
+        )?;
+
+        assert_eq!(
+            format_include_tree(&setup.path()).unwrap(),
+            "swhkd-test-tree1
+├── swhkd-test-tree2
+│   ├── swhkd-test-tree3
+│   └── swhkd-test-tree1 (cycle)
+├── swhkd-test-tree3 (duplicate)
+└── swhkd-test-tree-missing (not found)
+"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_glob_import() -> std::io::Result<()> {
+        let setup = TestPath::new("/tmp/swhkd-test-glob");
+        fs::create_dir_all(setup.path().join("conf.d"))?;
+
+        let mut f = File::create(setup.path().join("swhkdrc"))?;
+        f.write_all(
+            b"
+include conf.d/*.swhkd
+include empty.d/*.swhkd
+super + b
+   firefox",
+        )?;
+
+        let mut f2 = File::create(setup.path().join("conf.d/20-music.swhkd"))?;
+        f2.write_all(
+            b"
+super + m
+    mpc toggle",
+        )?;
+
+        let mut f3 = File::create(setup.path().join("conf.d/10-term.swhkd"))?;
+        f3.write_all(
+            b"
+super + t
+    alacritty",
+        )?;
+
+        File::create(setup.path().join("conf.d/README"))?;
+
+        let imports = Config::new(&setup.path().join("swhkdrc")).unwrap().imports;
+        assert_eq!(
+            imports,
+            vec![
+                setup.path().join("conf.d/10-term.swhkd"),
+                setup.path().join("conf.d/20-music.swhkd")
+            ]
+        );
+
+        let hotkeys = parse_config(&setup.path().join("swhkdrc"));
+        assert_eq!(
+            hotkeys.unwrap(),
+            vec!(
+                Hotkey::new(evdev::Key::KEY_B, vec![Modifier::Super], String::from("firefox")),
+                Hotkey::new(evdev::Key::KEY_T, vec![Modifier::Super], String::from("alacritty")),
+                Hotkey::new(evdev::Key::KEY_M, vec![Modifier::Super], String::from("mpc toggle"))
+            )
+        );
+        Ok(())
+    }
+
     #[test]
     fn test_more_multiple_configs() -> std::io::Result<()> {
         let setup = TestPath::new("/tmp/swhkd-test-file4");
@@ -231,7 +634,7 @@ d
     d",
         )?;
 
-        let hotkeys = load(&setup4.path()).unwrap();
+        let hotkeys = parse_config(&setup4.path()).unwrap();
         assert_eq!(
             hotkeys,
             vec!(
@@ -425,7 +828,7 @@ pesto
     }
 
     #[test]
-    // keysyms not followed by command should be ignored
+    // keysyms not followed by command are an error
     fn test_no_command() -> std::io::Result<()> {
         let contents = "
 k
@@ -435,14 +838,19 @@ w
 
                     ";
 
-        eval_config_test(
-            contents,
-            vec![Hotkey::new(
-                evdev::Key::KEY_K,
-                vec![],
-                "xbacklight -inc 10 -fps 30 -time 200".to_string(),
-            )],
-        )
+        eval_invalid_config_test(contents, ParseError::MissingCommand(PathBuf::new(), 5))
+    }
+
+    #[test]
+    fn test_keysym_followed_by_keysym() -> std::io::Result<()> {
+        let contents = "
+k
+
+w
+    xbacklight -inc 10 -fps 30 -time 200
+                    ";
+
+        eval_invalid_config_test(contents, ParseError::MissingCommand(PathBuf::new(), 2))
     }
 
     #[test]
@@ -523,21 +931,105 @@ k
     }
 
     #[test]
-    fn test_commented_out_keybind() -> std::io::Result<()> {
+    fn test_command_text_is_preserved() -> std::io::Result<()> {
         let contents = "
-#w
-    gimp
-                    ";
-
-        eval_config_test(contents, vec![])
-    }
+super + a
+    notify-send  'two  spaces'   \"  quoted \"
+super + b
+\tprintf '%s\\n'   \\
+\t\t  'first'\\
+        'second'    \\
 
-    // TODO: Write these tests as needed.
+        | sort";
 
-    #[test]
-    fn test_all_alphanumeric() -> std::io::Result<()> {
-        let symbols: [&str; 36] = [
-            "a", "b", "c", "d", "e", "f", "g", "h", "i", "j", "k", "l", "m", "n", "o", "p", "q",
+        eval_config_test(
+            contents,
+            vec![
+                Hotkey::new(
+                    evdev::Key::KEY_A,
+                    vec![Modifier::Super],
+                    String::from("notify-send  'two  spaces'   \"  quoted \""),
+                ),
+                Hotkey::new(
+                    evdev::Key::KEY_B,
+                    vec![Modifier::Super],
+                    String::from("printf '%s\\n' 'first' 'second' | sort"),
+                ),
+            ],
+        )
+    }
+
+    #[test]
+    fn test_join_lines() {
+        let contents = "
+super + \\
+shift + a
+    mpc ls | \\
+    dmenu
+b
+c
+    st";
+
+        assert_eq!(
+            join_lines(contents),
+            vec![
+                ("keysym", 1, "super + shift + a".to_string()),
+                ("command", 3, "mpc ls | dmenu".to_string()),
+                ("keysym", 5, "b".to_string()),
+                ("keysym", 6, "c".to_string()),
+                ("command", 7, "st".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_join_lines_trailing_backslash() -> std::io::Result<()> {
+        let contents = "
+super + a
+    notify-send hello \\";
+
+        assert_eq!(
+            join_lines(contents),
+            vec![
+                ("keysym", 1, "super + a".to_string()),
+                ("command", 2, "notify-send hello".to_string()),
+            ]
+        );
+
+        eval_config_test(
+            contents,
+            vec![Hotkey::new(
+                evdev::Key::KEY_A,
+                vec![Modifier::Super],
+                String::from("notify-send hello"),
+            )],
+        )
+    }
+
+    #[test]
+    fn test_join_lines_empty() -> std::io::Result<()> {
+        assert!(join_lines("").is_empty());
+        assert!(join_lines("# only \\\n# comments\n\n").is_empty());
+
+        eval_config_test("# only \\\n# comments \\\n", vec![])
+    }
+
+    #[test]
+    fn test_commented_out_keybind() -> std::io::Result<()> {
+        let contents = "
+#w
+    gimp
+                    ";
+
+        eval_config_test(contents, vec![])
+    }
+
+    // TODO: Write these tests as needed.
+
+    #[test]
+    fn test_all_alphanumeric() -> std::io::Result<()> {
+        let symbols: [&str; 36] = [
+            "a", "b", "c", "d", "e", "f", "g", "h", "i", "j", "k", "l", "m", "n", "o", "p", "q",
             "r", "s", "t", "u", "v", "w", "x", "y", "z", "0", "1", "2", "3", "4", "5", "6", "7",
             "8", "9",
         ];
@@ -620,172 +1112,980 @@ k
     }
 
     #[test]
-    fn test_case_insensitive() -> std::io::Result<()> {
+    fn test_numpad_and_lock_keys() -> std::io::Result<()> {
+        let symbols: [&str; 8] =
+            ["kp1", "kp_1", "KP_Enter", "kpenter", "kp_dot", "NumLock", "scrolllock", "Caps_Lock"];
+
+        let keysyms: [evdev::Key; 8] = [
+            evdev::Key::KEY_KP1,
+            evdev::Key::KEY_KP1,
+            evdev::Key::KEY_KPENTER,
+            evdev::Key::KEY_KPENTER,
+            evdev::Key::KEY_KPDOT,
+            evdev::Key::KEY_NUMLOCK,
+            evdev::Key::KEY_SCROLLLOCK,
+            evdev::Key::KEY_CAPSLOCK,
+        ];
+
+        for (symbol, keysym) in symbols.iter().zip(keysyms.iter()) {
+            let contents = format!("super + {}\n    st\n", symbol);
+            eval_config_test(
+                &contents,
+                vec![Hotkey::new(*keysym, vec![Modifier::Super], "st".to_string())],
+            )?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_keypad_equal_and_comma() -> std::io::Result<()> {
         let contents = "
-Super + SHIFT + alt + a
+kp_equal
     st
-ReTurn
+KP_Comma
     ts
             ";
+
         eval_config_test(
             contents,
             vec![
-                Hotkey::new(
-                    evdev::Key::KEY_A,
-                    vec![Modifier::Super, Modifier::Shift, Modifier::Alt],
-                    "st".to_string(),
-                ),
-                Hotkey::new(evdev::Key::KEY_ENTER, vec![], "ts".to_string()),
+                Hotkey::new(evdev::Key::KEY_KPEQUAL, vec![], "st".to_string()),
+                Hotkey::new(evdev::Key::KEY_KPCOMMA, vec![], "ts".to_string()),
             ],
         )
     }
 
     #[test]
-    fn test_duplicate_hotkeys() -> std::io::Result<()> {
+    fn test_mouse_buttons() -> std::io::Result<()> {
         let contents = "
-super + shift + a
+super + button3
     st
-shift + suPer +   A
+button1
     ts
-b    
+ctrl + btn_side
     st
-B
+            ";
+
+        eval_config_test(
+            contents,
+            vec![
+                Hotkey::new(evdev::Key::BTN_RIGHT, vec![Modifier::Super], "st".to_string()),
+                Hotkey::new(evdev::Key::BTN_LEFT, vec![], "ts".to_string()),
+                Hotkey::new(evdev::Key::BTN_SIDE, vec![Modifier::Control], "st".to_string()),
+            ],
+        )
+    }
+
+    #[test]
+    fn test_raw_keycode() -> std::io::Result<()> {
+        let contents = "
+super + code:190
+    st
+ctrl + 0xbf
     ts
-";
+            ";
+
         eval_config_test(
             contents,
             vec![
-                Hotkey::new(
-                    evdev::Key::KEY_A,
-                    vec![Modifier::Super, Modifier::Shift],
-                    "st".to_string(),
-                ),
-                Hotkey::new(evdev::Key::KEY_B, vec![], "st".to_string()),
+                Hotkey::new(evdev::Key::KEY_F20, vec![Modifier::Super], "st".to_string()),
+                Hotkey::new(evdev::Key::KEY_F21, vec![Modifier::Control], "ts".to_string()),
             ],
         )
     }
 
     #[test]
-    fn test_inline_comment() -> std::io::Result<()> {
+    fn test_raw_keycode_out_of_range() -> std::io::Result<()> {
         let contents = "
-super + a #comment and comment super
+super + code:1000
     st
-super + shift + b
-    ts #this comment should be handled by shell
-"
-        .to_string();
+            ";
+
+        eval_invalid_config_test(contents, ParseError::InvalidKeysym(PathBuf::new(), 2))
+    }
+
+    #[test]
+    fn test_sided_modifiers() -> std::io::Result<()> {
+        let contents = "
+lshift + a
+    st
+rshift + a
+    ts
+lctrl + ralt + b
+    st
+            ";
+
         eval_config_test(
-            &contents,
+            contents,
             vec![
-                Hotkey::new(evdev::Key::KEY_A, vec![Modifier::Super], "st".to_string()),
+                Hotkey::new(evdev::Key::KEY_A, vec![Modifier::LeftShift], "st".to_string()),
+                Hotkey::new(evdev::Key::KEY_A, vec![Modifier::RightShift], "ts".to_string()),
                 Hotkey::new(
                     evdev::Key::KEY_B,
-                    vec![Modifier::Super, Modifier::Shift],
-                    "ts #this comment should be handled by shell".to_string(),
+                    vec![Modifier::LeftControl, Modifier::RightAlt],
+                    "st".to_string(),
                 ),
             ],
         )
     }
 
     #[test]
-    fn test_blank_config() -> std::io::Result<()> {
-        let contents = "";
+    fn test_generic_modifier_matches_both_sides() {
+        let shift = KeyBinding::new(evdev::Key::KEY_A, vec![Modifier::Shift]);
+        let left_shift = KeyBinding::new(evdev::Key::KEY_A, vec![Modifier::LeftShift]);
+
+        assert!(shift.matches_modifiers(&[Modifier::LeftShift]));
+        assert!(shift.matches_modifiers(&[Modifier::RightShift]));
+        assert!(left_shift.matches_modifiers(&[Modifier::LeftShift]));
+        assert!(!left_shift.matches_modifiers(&[Modifier::RightShift]));
+        assert!(!shift.matches_modifiers(&[Modifier::LeftShift, Modifier::RightShift]));
+        assert!(KeyBinding::new(evdev::Key::KEY_A, vec![Modifier::Super, Modifier::Control])
+            .matches_modifiers(&[Modifier::RightControl, Modifier::Super]));
+    }
 
-        eval_config_test(contents, vec![])
+    #[test]
+    fn test_modifiers_match_one_to_one() {
+        let shift_alt = KeyBinding::new(evdev::Key::KEY_A, vec![Modifier::Shift, Modifier::Alt]);
+        let both_shifts =
+            KeyBinding::new(evdev::Key::KEY_A, vec![Modifier::LeftShift, Modifier::RightShift]);
+
+        assert!(!shift_alt.matches_modifiers(&[Modifier::LeftShift, Modifier::RightShift]));
+        assert!(shift_alt.matches_modifiers(&[Modifier::RightShift, Modifier::LeftAlt]));
+        assert!(both_shifts.matches_modifiers(&[Modifier::RightShift, Modifier::LeftShift]));
+
+        // Equality compares what is written, in both directions
+        assert_ne!(shift_alt, both_shifts);
+        assert_ne!(both_shifts, shift_alt);
+        assert_ne!(
+            shift_alt,
+            KeyBinding::new(evdev::Key::KEY_A, vec![Modifier::LeftShift, Modifier::Alt])
+        );
+        assert_eq!(
+            shift_alt,
+            KeyBinding::new(evdev::Key::KEY_A, vec![Modifier::Alt, Modifier::Shift])
+        );
     }
 
     #[test]
-    fn test_blank_config_with_whitespace() -> std::io::Result<()> {
+    fn test_sided_binding_is_not_a_duplicate() -> std::io::Result<()> {
         let contents = "
+lshift + rshift + a
+    st
+shift + alt + a
+    ts";
 
+        eval_config_test(
+            contents,
+            vec![
+                Hotkey::new(
+                    evdev::Key::KEY_A,
+                    vec![Modifier::LeftShift, Modifier::RightShift],
+                    "st".to_string(),
+                ),
+                Hotkey::new(
+                    evdev::Key::KEY_A,
+                    vec![Modifier::Shift, Modifier::Alt],
+                    "ts".to_string(),
+                ),
+            ],
+        )
+    }
 
+    #[test]
+    fn test_any_modifier() -> std::io::Result<()> {
+        let contents = "
+any + XF86AudioMute
+    pamixer -t
             ";
 
-        eval_config_test(contents, vec![])
+        let hotkeys = parse_contents(PathBuf::new(), contents.to_string()).unwrap();
+        assert_eq!(hotkeys.len(), 1);
+        let keybinding = &hotkeys[0].keybinding;
+        assert!(keybinding.match_any_modifier);
+        assert!(keybinding.modifiers.is_empty());
+
+        assert!(keybinding.matches_modifiers(&[]));
+        assert!(keybinding.matches_modifiers(&[Modifier::Super]));
+        assert!(keybinding.matches_modifiers(&[Modifier::Control, Modifier::Shift]));
+        assert_ne!(*keybinding, KeyBinding::new(evdev::Key::KEY_MUTE, vec![]));
+        Ok(())
     }
 
     #[test]
-    fn test_extract_curly_brace() -> std::io::Result<()> {
-        let keybind_with_curly_brace = "super + {a,b,c}";
+    fn test_any_modifier_is_not_a_duplicate() -> std::io::Result<()> {
+        let any_first = "
+any + m
+    a
+super + m
+    b";
+        let super_first = "
+super + m
+    b
+any + m
+    a";
+
+        for contents in [any_first, super_first] {
+            let hotkeys = parse_contents(PathBuf::new(), contents.to_string()).unwrap();
+            let mut commands: Vec<&str> =
+                hotkeys.iter().map(|hotkey| hotkey.command.as_str()).collect();
+            commands.sort();
+            assert_eq!(commands, vec!["a", "b"]);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_any_modifier_with_other_modifiers() -> std::io::Result<()> {
+        let contents = "
+super + any + XF86AudioMute
+    pamixer -t
+            ";
+
+        eval_invalid_config_test(contents, ParseError::InvalidModifier(PathBuf::new(), 2))
+    }
+
+    #[test]
+    fn test_chord() -> std::io::Result<()> {
+        let contents = "
+super + c ; super + k
+    emacs
+
+super + ;
+    rofi -show window
+
+super + ; ; b
+    st";
+
+        let result = parse_contents(PathBuf::new(), contents.to_string()).unwrap();
+        assert_eq!(result.len(), 3);
+
+        assert_eq!(result[0].keybinding, KeyBinding::new(evdev::Key::KEY_K, vec![Modifier::Super]));
         assert_eq!(
-            extract_curly_brace(keybind_with_curly_brace),
-            vec!["super + a", "super + b", "super + c",]
+            result[0].chord,
+            Some(Chord(vec![
+                KeyBinding::new(evdev::Key::KEY_C, vec![Modifier::Super]),
+                KeyBinding::new(evdev::Key::KEY_K, vec![Modifier::Super]),
+            ]))
         );
-        let command_with_curly_brace = "bspc node -p {west,south,north,west}";
+
         assert_eq!(
-            extract_curly_brace(command_with_curly_brace),
-            vec![
-                "bspc node -p west",
-                "bspc node -p south",
-                "bspc node -p north",
-                "bspc node -p west",
-            ]
+            result[1].keybinding,
+            KeyBinding::new(evdev::Key::KEY_SEMICOLON, vec![Modifier::Super])
+        );
+        assert_eq!(result[1].chord, None);
+
+        assert_eq!(
+            result[2].chord,
+            Some(Chord(vec![
+                KeyBinding::new(evdev::Key::KEY_SEMICOLON, vec![Modifier::Super]),
+                KeyBinding::new(evdev::Key::KEY_B, vec![]),
+            ]))
         );
-        let wrong_format = "super + }a, b, c{";
-        assert_eq!(extract_curly_brace(wrong_format), vec![wrong_format]);
-        let single_sym = "super + {a}";
-        assert_eq!(extract_curly_brace(single_sym), vec!["super + a"]);
         Ok(())
     }
 
     #[test]
-    fn test_curly_brace() -> std::io::Result<()> {
+    fn test_chord_does_not_shadow_keybinding() -> std::io::Result<()> {
         let contents = "
-super + {a,b,c}
-    {firefox, brave, chrome}";
-        eval_config_test(
-            contents,
-            vec![
-                Hotkey::new(evdev::Key::KEY_A, vec![Modifier::Super], "firefox".to_string()),
-                Hotkey::new(evdev::Key::KEY_B, vec![Modifier::Super], "brave".to_string()),
-                Hotkey::new(evdev::Key::KEY_C, vec![Modifier::Super], "chrome".to_string()),
-            ],
-        )
+super + c ; k
+    emacs
+
+k
+    st";
+
+        let result = parse_contents(PathBuf::new(), contents.to_string()).unwrap();
+        assert_eq!(result.len(), 2);
+        assert!(result[0].chord.is_some());
+        assert_eq!(result[1].chord, None);
+        Ok(())
     }
 
     #[test]
-    fn test_curly_brace_less_commands() -> std::io::Result<()> {
+    fn test_chord_empty_segment() -> std::io::Result<()> {
         let contents = "
-super + {a,b,c}
-    {firefox, brave}";
-        eval_config_test(
-            contents,
-            vec![
-                Hotkey::new(evdev::Key::KEY_A, vec![Modifier::Super], "firefox".to_string()),
-                Hotkey::new(evdev::Key::KEY_B, vec![Modifier::Super], "brave".to_string()),
-            ],
-        )
+a ; ; b
+    st";
+
+        eval_invalid_config_test(contents, ParseError::InvalidChord(PathBuf::new(), 2))
     }
 
     #[test]
-    fn test_curly_brace_less_keysyms() -> std::io::Result<()> {
+    fn test_duplicate_modifier() -> std::io::Result<()> {
         let contents = "
-super + {a, b}
-    {firefox, brave, chrome}";
-        eval_config_test(
-            contents,
-            vec![
-                Hotkey::new(evdev::Key::KEY_A, vec![Modifier::Super], "firefox".to_string()),
-                Hotkey::new(evdev::Key::KEY_B, vec![Modifier::Super], "brave".to_string()),
-            ],
-        )
+super + a
+    st
+
+ctrl + control + a
+    st";
+
+        eval_invalid_config_test(contents, ParseError::DuplicateModifier(PathBuf::new(), 5))
     }
 
     #[test]
-    fn test_range_syntax() -> std::io::Result<()> {
+    fn test_sided_modifier_next_to_generic_is_duplicate() -> std::io::Result<()> {
         let contents = "
-super + {1-9,0}
-    bspc desktop -f '{1-9,0}'";
-        eval_config_test(
-            contents,
+shift + lshift + a
+    st";
+
+        eval_invalid_config_test(contents, ParseError::DuplicateModifier(PathBuf::new(), 2))
+    }
+
+    #[test]
+    fn test_empty_token() -> std::io::Result<()> {
+        let contents = "
+super + + a
+    st";
+
+        eval_invalid_config_test(contents, ParseError::EmptyToken(PathBuf::new(), 2))
+    }
+
+    #[test]
+    fn test_alias() -> std::io::Result<()> {
+        let contents = "
+alias hyper = super
+alias copilot = code:98
+alias launcher = hyper
+
+hyper + Return
+    alacritty
+
+ctrl + @copilot
+    notify-send copilot
+
+launcher + d
+    dmenu_run";
+
+        let result = parse_contents(PathBuf::new(), contents.to_string()).unwrap();
+        assert_eq!(
+            result,
             vec![
                 Hotkey::new(
-                    evdev::Key::KEY_1,
+                    evdev::Key::KEY_ENTER,
                     vec![Modifier::Super],
-                    "bspc desktop -f '1'".to_string(),
+                    String::from("alacritty")
                 ),
                 Hotkey::new(
-                    evdev::Key::KEY_2,
+                    evdev::Key::new(98),
+                    vec![Modifier::Control],
+                    String::from("notify-send copilot")
+                )
+                .on_release(),
+                Hotkey::new(evdev::Key::KEY_D, vec![Modifier::Super], String::from("dmenu_run")),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_alias_redefined() -> std::io::Result<()> {
+        let contents = "
+alias mod = super
+mod + a
+    st
+
+alias mod = alt
+mod + b
+    firefox";
+
+        eval_config_test(
+            contents,
+            vec![
+                Hotkey::new(evdev::Key::KEY_A, vec![Modifier::Super], String::from("st")),
+                Hotkey::new(evdev::Key::KEY_B, vec![Modifier::Alt], String::from("firefox")),
+            ],
+        )
+    }
+
+    #[test]
+    fn test_alias_in_include() -> std::io::Result<()> {
+        let setup = TestPath::new("/tmp/swhkd-test-alias1");
+        let mut f = File::create(setup.path())?;
+        f.write_all(b"alias hyper = super\ninclude /tmp/swhkd-test-alias2\n")?;
+
+        let setup2 = TestPath::new("/tmp/swhkd-test-alias2");
+        let mut f2 = File::create(setup2.path())?;
+        f2.write_all(b"hyper + a\n    st\n")?;
+
+        assert_eq!(
+            parse_config(&setup.path()).unwrap(),
+            vec![Hotkey::new(evdev::Key::KEY_A, vec![Modifier::Super], String::from("st"))]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_alias_from_include() -> std::io::Result<()> {
+        let setup = TestPath::new("/tmp/swhkd-test-alias3");
+        let mut f = File::create(setup.path())?;
+        f.write_all(b"include /tmp/swhkd-test-alias4\nhyper + a\n    st\n")?;
+
+        let setup2 = TestPath::new("/tmp/swhkd-test-alias4");
+        let mut f2 = File::create(setup2.path())?;
+        f2.write_all(b"alias hyper = super\n")?;
+
+        assert_eq!(
+            parse_config(&setup.path()).unwrap(),
+            vec![Hotkey::new(evdev::Key::KEY_A, vec![Modifier::Super], String::from("st"))]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_alias_redefined_in_include() -> std::io::Result<()> {
+        let setup = TestPath::new("/tmp/swhkd-test-alias5");
+        let mut f = File::create(setup.path())?;
+        f.write_all(b"alias mod = super\ninclude /tmp/swhkd-test-alias6\nmod + a\n    st\n")?;
+
+        let setup2 = TestPath::new("/tmp/swhkd-test-alias6");
+        let mut f2 = File::create(setup2.path())?;
+        f2.write_all(b"alias mod = alt\nmod + b\n    firefox\n")?;
+
+        assert_eq!(
+            parse_config(&setup.path()).unwrap(),
+            vec![
+                Hotkey::new(evdev::Key::KEY_A, vec![Modifier::Super], String::from("st")),
+                Hotkey::new(evdev::Key::KEY_B, vec![Modifier::Alt], String::from("firefox")),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_alias() -> std::io::Result<()> {
+        eval_invalid_config_test(
+            "alias hyper = hyperr\nhyper + a\n    st",
+            ParseError::UndefinedAlias(PathBuf::new(), 1),
+        )?;
+        eval_invalid_config_test(
+            "alias super = alt\nsuper + a\n    st",
+            ParseError::InvalidAlias(PathBuf::new(), 1),
+        )?;
+        eval_invalid_config_test("alias hyper super", ParseError::InvalidAlias(PathBuf::new(), 1))
+    }
+
+    #[test]
+    fn test_reserved_alias_name() -> std::io::Result<()> {
+        for name in ["any", "include", "alias", "device", "ignore"] {
+            eval_invalid_config_test(
+                &format!("alias {} = super\nsuper + a\n    st", name),
+                ParseError::InvalidAlias(PathBuf::new(), 1),
+            )?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_modifier_only() -> std::io::Result<()> {
+        let contents = "
+super
+    rofi -show drun
+
+ctrl + shift
+    notify-send layout";
+
+        let result = parse_contents(PathBuf::new(), contents.to_string()).unwrap();
+        assert_eq!(
+            result.iter().map(|hotkey| hotkey.keybinding.clone()).collect::<Vec<_>>(),
+            vec![
+                KeyBinding::modifier_only(vec![Modifier::Super]),
+                KeyBinding::modifier_only(vec![Modifier::Control, Modifier::Shift]),
+            ]
+        );
+        assert_eq!(result[0].keybinding.keysym, None);
+
+        eval_invalid_config_test("@super\n    st", ParseError::InvalidKeysym(PathBuf::new(), 1))?;
+        eval_invalid_config_test(
+            "any + super\n    st",
+            ParseError::InvalidModifier(PathBuf::new(), 1),
+        )
+    }
+
+    #[test]
+    fn test_repeated_modifier() -> std::io::Result<()> {
+        let contents = "
+ctrl*2
+    notify-send panic
+super*3 [timeout=500]
+    swaylock";
+
+        let result = parse_contents(PathBuf::new(), contents.to_string()).unwrap();
+        let mut double = KeyBinding::modifier_only(vec![Modifier::Control]);
+        double.repeat = 2;
+        let mut triple = KeyBinding::modifier_only(vec![Modifier::Super]);
+        triple.repeat = 3;
+        assert_eq!(result[0].keybinding, double);
+        assert_eq!(result[0].timeout_ms, None);
+        assert_eq!(result[1].keybinding, triple);
+        assert_eq!(result[1].timeout_ms, Some(500));
+        assert_ne!(double, KeyBinding::modifier_only(vec![Modifier::Control]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_repeated_modifier_not_alone() -> std::io::Result<()> {
+        for contents in ["ctrl*2 + a\n    st", "ctrl*2 + shift\n    st", "ctrl*1\n    st"] {
+            eval_invalid_config_test(contents, ParseError::InvalidModifier(PathBuf::new(), 1))?;
+        }
+        // `*` on its own is still shift + 8
+        eval_config_test(
+            "super + *\n    st",
+            vec![Hotkey::new(
+                evdev::Key::KEY_8,
+                vec![Modifier::Super, Modifier::Shift],
+                "st".to_string(),
+            )],
+        )?;
+        eval_invalid_config_test(
+            "super + a [timeout=500]\n    st",
+            ParseError::InvalidAnnotation(PathBuf::new(), 1),
+        )
+    }
+
+    #[test]
+    fn test_empty_keybinding() -> std::io::Result<()> {
+        eval_invalid_config_test("_\n    st", ParseError::UnknownSymbol(PathBuf::new(), 1))?;
+        eval_invalid_config_test("[sticky]\n    st", ParseError::UnknownSymbol(PathBuf::new(), 1))
+    }
+
+    #[test]
+    fn test_device_scope() -> std::io::Result<()> {
+        let contents = "
+super + a
+    st
+
+device \"My Macropad\" # the one on the left
+super + a
+    obs-cmd scene switch Main
+f13
+    obs-cmd recording toggle
+
+device laptop-keyboard
+f13
+    notify-send laptop
+
+device
+f14
+    notify-send all";
+
+        let result = parse_contents(PathBuf::new(), contents.to_string()).unwrap();
+        let scopes: Vec<(&str, Option<&str>)> = result
+            .iter()
+            .map(|hotkey| (hotkey.command.as_str(), hotkey.device.as_deref()))
+            .collect();
+        assert_eq!(
+            scopes,
+            vec![
+                ("st", None),
+                ("obs-cmd scene switch Main", Some("My Macropad")),
+                ("obs-cmd recording toggle", Some("My Macropad")),
+                ("notify-send laptop", Some("laptop-keyboard")),
+                ("notify-send all", None),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_device_scope_ends_with_file() -> std::io::Result<()> {
+        let setup = TestPath::new("/tmp/swhkd-test-device1");
+        let mut f = File::create(setup.path())?;
+        f.write_all(b"include /tmp/swhkd-test-device2\ndevice macropad\nf13\n    st\n")?;
+
+        let setup2 = TestPath::new("/tmp/swhkd-test-device2");
+        let mut f2 = File::create(setup2.path())?;
+        f2.write_all(b"f14\n    st\n")?;
+
+        let hotkeys = parse_config(&setup.path()).unwrap();
+        assert_eq!(hotkeys[0].device.as_deref(), Some("macropad"));
+        assert_eq!(hotkeys[1].device, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_device() -> std::io::Result<()> {
+        eval_invalid_config_test(
+            "device \"Macropad\nf13\n    st",
+            ParseError::InvalidDevice(PathBuf::new(), 1),
+        )?;
+        eval_invalid_config_test(
+            "device My Macropad\nf13\n    st",
+            ParseError::InvalidDevice(PathBuf::new(), 1),
+        )
+    }
+
+    #[test]
+    fn test_find_conflicts() {
+        let hotkeys = vec![
+            Hotkey::new(
+                evdev::Key::KEY_ENTER,
+                vec![Modifier::Super, Modifier::Shift],
+                String::from("alacritty"),
+            ),
+            Hotkey::new(evdev::Key::KEY_W, vec![Modifier::Super], String::from("firefox")),
+            Hotkey::new(
+                evdev::Key::KEY_ENTER,
+                vec![Modifier::Shift, Modifier::Super],
+                String::from("kitty"),
+            ),
+            // Same command, so nothing is lost
+            Hotkey::new(evdev::Key::KEY_W, vec![Modifier::Super], String::from("firefox")),
+        ];
+
+        assert_eq!(find_conflicts(&hotkeys), vec![(hotkeys[0].clone(), hotkeys[2].clone())]);
+
+        let mut scoped = hotkeys[2].clone();
+        scoped.device = Some(String::from("macropad"));
+        assert!(find_conflicts(&[hotkeys[0].clone(), scoped]).is_empty());
+    }
+
+    #[test]
+    fn test_find_conflicts_normalized() {
+        let mut first =
+            Hotkey::new(evdev::Key::KEY_W, vec![Modifier::Super], String::from("Firefox"));
+        let mut second =
+            Hotkey::new(evdev::Key::KEY_W, vec![Modifier::Super], String::from("  firefox"));
+        assert_eq!(find_conflicts(&[first.clone(), second.clone()]).len(), 1);
+
+        first.normalized_command = Some(normalize_command(&first.command));
+        second.normalized_command = Some(normalize_command(&second.command));
+        assert!(find_conflicts(&[first, second]).is_empty());
+    }
+
+    #[test]
+    fn test_ignore_inherited_binding() -> std::io::Result<()> {
+        let setup = TestPath::new("/tmp/swhkd-test-ignore1");
+        let mut f = File::create(setup.path())?;
+        f.write_all(
+            b"
+ignore super + q
+include /tmp/swhkd-test-ignore2
+
+super + w
+    firefox
+",
+        )?;
+
+        let setup2 = TestPath::new("/tmp/swhkd-test-ignore2");
+        let mut f2 = File::create(setup2.path())?;
+        f2.write_all(
+            b"
+super + q
+    bspc node -c
+
+super + {1,2}
+    bspc desktop -f {1,2}
+
+ignore super + 2
+",
+        )?;
+
+        let hotkeys = parse_config(&setup.path()).unwrap();
+        assert_eq!(
+            hotkeys.iter().map(|hotkey| hotkey.command.as_str()).collect::<Vec<_>>(),
+            vec!["firefox", "bspc desktop -f 1"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_ignore() -> std::io::Result<()> {
+        let contents = "
+super + {a,b,c}
+    st {a,b,c}
+
+ignore super + {a,c} # both of them
+ignore ctrl + x";
+
+        eval_config_test(
+            contents,
+            vec![Hotkey::new(evdev::Key::KEY_B, vec![Modifier::Super], String::from("st b"))],
+        )?;
+        eval_invalid_config_test(
+            "ignore super + pesto",
+            ParseError::UnknownSymbol(PathBuf::new(), 1),
+        )
+    }
+
+    #[test]
+    fn test_keybinding_hash() {
+        fn hash(keybinding: &KeyBinding) -> u64 {
+            let mut hasher = DefaultHasher::new();
+            keybinding.hash(&mut hasher);
+            hasher.finish()
+        }
+
+        let first = KeyBinding::new(evdev::Key::KEY_A, vec![Modifier::Super, Modifier::Shift]);
+        let second = KeyBinding::new(evdev::Key::KEY_A, vec![Modifier::Shift, Modifier::Super]);
+        assert_eq!(first, second);
+        assert_eq!(hash(&first), hash(&second));
+        assert_eq!(first.normalized().modifiers, second.normalized().modifiers);
+        assert_eq!(
+            KeyBinding::new(evdev::Key::KEY_A, vec![Modifier::Shift, Modifier::Shift])
+                .normalized()
+                .modifiers,
+            vec![Modifier::Shift]
+        );
+
+        let hotkeys: HashMap<KeyBinding, String> = HashMap::from([
+            (first, "st".to_string()),
+            (KeyBinding::new(evdev::Key::KEY_A, vec![Modifier::Super]), "ts".to_string()),
+        ]);
+        assert_eq!(hotkeys.get(&second), Some(&"st".to_string()));
+        assert_eq!(
+            hotkeys.get(&KeyBinding::new(evdev::Key::KEY_A, vec![Modifier::Super])),
+            Some(&"ts".to_string())
+        );
+        assert_eq!(hotkeys.get(&KeyBinding::new(evdev::Key::KEY_A, vec![])), None);
+    }
+
+    #[test]
+    fn test_keybinding_eq_is_exact() {
+        let shift = KeyBinding::new(evdev::Key::KEY_A, vec![Modifier::Shift]);
+        let left = KeyBinding::new(evdev::Key::KEY_A, vec![Modifier::LeftShift]);
+        let right = KeyBinding::new(evdev::Key::KEY_A, vec![Modifier::RightShift]);
+        assert_ne!(shift, left);
+        assert_ne!(shift, right);
+        assert_ne!(left, right);
+
+        let mut any = KeyBinding::new(evdev::Key::KEY_A, vec![]);
+        any.match_any_modifier = true;
+        assert_ne!(any, KeyBinding::new(evdev::Key::KEY_A, vec![]));
+        assert_ne!(any, shift);
+    }
+
+    #[test]
+    fn test_case_insensitive() -> std::io::Result<()> {
+        let contents = "
+Super + SHIFT + alt + a
+    st
+ReTurn
+    ts
+            ";
+        eval_config_test(
+            contents,
+            vec![
+                Hotkey::new(
+                    evdev::Key::KEY_A,
+                    vec![Modifier::Super, Modifier::Shift, Modifier::Alt],
+                    "st".to_string(),
+                ),
+                Hotkey::new(evdev::Key::KEY_ENTER, vec![], "ts".to_string()),
+            ],
+        )
+    }
+
+    #[test]
+    fn test_duplicate_hotkeys() -> std::io::Result<()> {
+        let contents = "
+super + shift + a
+    st
+shift + suPer +   A
+    ts
+b    
+    st
+B
+    ts
+";
+        eval_config_test(
+            contents,
+            vec![
+                Hotkey::new(
+                    evdev::Key::KEY_A,
+                    vec![Modifier::Super, Modifier::Shift],
+                    "st".to_string(),
+                ),
+                Hotkey::new(evdev::Key::KEY_B, vec![], "st".to_string()),
+                // An uppercase letter holds shift, so this isn't a duplicate
+                Hotkey::new(evdev::Key::KEY_B, vec![Modifier::Shift], "ts".to_string()),
+            ],
+        )
+    }
+
+    #[test]
+    fn test_implied_shift() -> std::io::Result<()> {
+        let contents = "
+super + Q
+    bspc quit
+shift + W
+    st
+!
+    notify-send bang
+ctrl + @?
+    help
+q
+    nvim";
+
+        let result = parse_contents(PathBuf::new(), contents.to_string()).unwrap();
+        assert_eq!(
+            result,
+            vec![
+                Hotkey::new(
+                    evdev::Key::KEY_Q,
+                    vec![Modifier::Super, Modifier::Shift],
+                    "bspc quit".to_string()
+                ),
+                Hotkey::new(evdev::Key::KEY_W, vec![Modifier::Shift], "st".to_string()),
+                Hotkey::new(
+                    evdev::Key::KEY_1,
+                    vec![Modifier::Shift],
+                    "notify-send bang".to_string()
+                ),
+                Hotkey::new(
+                    evdev::Key::KEY_SLASH,
+                    vec![Modifier::Control, Modifier::Shift],
+                    "help".to_string()
+                )
+                .on_release(),
+                Hotkey::new(evdev::Key::KEY_Q, vec![], "nvim".to_string()),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_implied_shift_after_placeholder() -> std::io::Result<()> {
+        let contents = "
+super + {_,ctrl + }H
+    {st,ts}";
+
+        eval_config_test(
+            contents,
+            vec![
+                Hotkey::new(
+                    evdev::Key::KEY_H,
+                    vec![Modifier::Super, Modifier::Shift],
+                    "st".to_string(),
+                ),
+                Hotkey::new(
+                    evdev::Key::KEY_H,
+                    vec![Modifier::Super, Modifier::Control, Modifier::Shift],
+                    "ts".to_string(),
+                ),
+            ],
+        )
+    }
+
+    #[test]
+    fn test_inline_comment() -> std::io::Result<()> {
+        let contents = "
+super + a #comment and comment super
+    st
+super + shift + b
+    ts #this comment should be handled by shell
+"
+        .to_string();
+        eval_config_test(
+            &contents,
+            vec![
+                Hotkey::new(evdev::Key::KEY_A, vec![Modifier::Super], "st".to_string()),
+                Hotkey::new(
+                    evdev::Key::KEY_B,
+                    vec![Modifier::Super, Modifier::Shift],
+                    "ts #this comment should be handled by shell".to_string(),
+                ),
+            ],
+        )
+    }
+
+    #[test]
+    fn test_blank_config() -> std::io::Result<()> {
+        let contents = "";
+
+        eval_config_test(contents, vec![])
+    }
+
+    #[test]
+    fn test_blank_config_with_whitespace() -> std::io::Result<()> {
+        let contents = "
+
+
+            ";
+
+        eval_config_test(contents, vec![])
+    }
+
+    #[test]
+    fn test_extract_curly_brace() -> std::io::Result<()> {
+        let keybind_with_curly_brace = "super + {a,b,c}";
+        assert_eq!(
+            extract_curly_brace(keybind_with_curly_brace),
+            vec!["super + a", "super + b", "super + c",]
+        );
+        let command_with_curly_brace = "bspc node -p {west,south,north,west}";
+        assert_eq!(
+            extract_curly_brace(command_with_curly_brace),
+            vec![
+                "bspc node -p west",
+                "bspc node -p south",
+                "bspc node -p north",
+                "bspc node -p west",
+            ]
+        );
+        let wrong_format = "super + }a, b, c{";
+        assert_eq!(extract_curly_brace(wrong_format), vec![wrong_format]);
+        let single_sym = "super + {a}";
+        assert_eq!(extract_curly_brace(single_sym), vec!["super + a"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_curly_brace() -> std::io::Result<()> {
+        let contents = "
+super + {a,b,c}
+    {firefox, brave, chrome}";
+        eval_config_test(
+            contents,
+            vec![
+                Hotkey::new(evdev::Key::KEY_A, vec![Modifier::Super], "firefox".to_string()),
+                Hotkey::new(evdev::Key::KEY_B, vec![Modifier::Super], "brave".to_string()),
+                Hotkey::new(evdev::Key::KEY_C, vec![Modifier::Super], "chrome".to_string()),
+            ],
+        )
+    }
+
+    #[test]
+    fn test_curly_brace_less_commands() -> std::io::Result<()> {
+        let contents = "
+super + {a,b,c}
+    {firefox, brave}";
+        eval_config_test(
+            contents,
+            vec![
+                Hotkey::new(evdev::Key::KEY_A, vec![Modifier::Super], "firefox".to_string()),
+                Hotkey::new(evdev::Key::KEY_B, vec![Modifier::Super], "brave".to_string()),
+            ],
+        )
+    }
+
+    #[test]
+    fn test_curly_brace_less_keysyms() -> std::io::Result<()> {
+        let contents = "
+super + {a, b}
+    {firefox, brave, chrome}";
+        eval_config_test(
+            contents,
+            vec![
+                Hotkey::new(evdev::Key::KEY_A, vec![Modifier::Super], "firefox".to_string()),
+                Hotkey::new(evdev::Key::KEY_B, vec![Modifier::Super], "brave".to_string()),
+            ],
+        )
+    }
+
+    #[test]
+    fn test_range_syntax() -> std::io::Result<()> {
+        let contents = "
+super + {1-9,0}
+    bspc desktop -f '{1-9,0}'";
+        eval_config_test(
+            contents,
+            vec![
+                Hotkey::new(
+                    evdev::Key::KEY_1,
+                    vec![Modifier::Super],
+                    "bspc desktop -f '1'".to_string(),
+                ),
+                Hotkey::new(
+                    evdev::Key::KEY_2,
                     vec![Modifier::Super],
                     "bspc desktop -f '2'".to_string(),
                 ),
@@ -1064,76 +2364,325 @@ super + {_, ctrl +} {_, shift +} {1-2}
     }
 
     #[test]
-    fn test_period_binding() -> std::io::Result<()> {
+    fn test_period_binding() -> std::io::Result<()> {
+        let contents = "
+super + {comma, period}
+	riverctl focus-output {previous, next}";
+
+        eval_config_test(
+            contents,
+            vec![
+                Hotkey::new(
+                    evdev::Key::KEY_COMMA,
+                    vec![Modifier::Super],
+                    "riverctl focus-output previous".to_string(),
+                ),
+                Hotkey::new(
+                    evdev::Key::KEY_DOT,
+                    vec![Modifier::Super],
+                    "riverctl focus-output next".to_string(),
+                ),
+            ],
+        )
+    }
+
+    #[test]
+    fn test_period_escape_binding() -> std::io::Result<()> {
+        let contents = "
+super + {\\,, .}
+	riverctl focus-output {previous, next}";
+
+        eval_config_test(
+            contents,
+            vec![
+                Hotkey::new(
+                    evdev::Key::KEY_COMMA,
+                    vec![Modifier::Super],
+                    "riverctl focus-output previous".to_string(),
+                ),
+                Hotkey::new(
+                    evdev::Key::KEY_DOT,
+                    vec![Modifier::Super],
+                    "riverctl focus-output next".to_string(),
+                ),
+            ],
+        )
+    }
+
+    #[test]
+    fn test_prefix() -> std::io::Result<()> {
+        let contents = "
+super + @1
+    1
+super + ~2
+    2
+super + ~@3
+    3
+super + @~4
+    4";
+
+        eval_config_test(
+            contents,
+            vec![
+                Hotkey::new(evdev::Key::KEY_1, vec![Modifier::Super], "1".to_string()).on_release(),
+                Hotkey::new(evdev::Key::KEY_2, vec![Modifier::Super], "2".to_string()).send(),
+                Hotkey::new(evdev::Key::KEY_3, vec![Modifier::Super], "3".to_string())
+                    .on_release()
+                    .send(),
+                Hotkey::new(evdev::Key::KEY_4, vec![Modifier::Super], "4".to_string())
+                    .on_release()
+                    .send(),
+            ],
+        )
+    }
+
+    #[test]
+    fn test_cooldown_annotation() -> std::io::Result<()> {
+        let contents = "
+super + Print [cooldown=300]
+    grim
+super + p
+    hyprpicker";
+
+        let hotkeys = parse_contents(PathBuf::new(), contents.to_string()).unwrap();
+        assert_eq!(hotkeys.len(), 2);
+        assert_eq!(
+            hotkeys[0].keybinding,
+            KeyBinding::new(evdev::Key::KEY_SYSRQ, vec![Modifier::Super])
+        );
+        assert_eq!(hotkeys[0].cooldown_ms, Some(300));
+        assert_eq!(hotkeys[1].cooldown_ms, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_cooldown_annotation() -> std::io::Result<()> {
+        let contents = "
+super + Print [cooldown=soon]
+    grim";
+
+        eval_invalid_config_test(contents, ParseError::InvalidAnnotation(PathBuf::new(), 2))
+    }
+
+    #[test]
+    fn test_route_annotation() -> std::io::Result<()> {
+        let contents = "
+super + a [via=swhks]
+    st
+super + b [via=direct]
+    st
+super + c
+    st";
+
+        let hotkeys = parse_contents(PathBuf::new(), contents.to_string()).unwrap();
+        assert_eq!(
+            hotkeys.iter().map(|hotkey| hotkey.route).collect::<Vec<Route>>(),
+            vec![Route::Swhks, Route::Direct, Route::Swhks]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_route_annotation() -> std::io::Result<()> {
+        let contents = "
+super + a [via=ssh]
+    st";
+
+        eval_invalid_config_test(contents, ParseError::InvalidAnnotation(PathBuf::new(), 2))
+    }
+
+    #[test]
+    fn test_repeated_annotation() -> std::io::Result<()> {
+        let contents = "
+super + a [via=Direct] [via=swhks]
+    st";
+
+        eval_invalid_config_test(contents, ParseError::InvalidAnnotation(PathBuf::new(), 2))
+    }
+
+    #[test]
+    fn test_normalized_command_duplicates() -> std::io::Result<()> {
+        let contents = "
+super + f
+    Firefox --new-window
+super + b
+    firefox --new-window
+super + c
+    chromium
+            ";
+
+        let hotkeys = parse_contents(PathBuf::new(), contents.to_string()).unwrap();
+        assert!(find_duplicate_commands(&hotkeys).is_empty());
+
+        let options = ParseOptions { normalize_commands: true, ..Default::default() };
+        let hotkeys =
+            parse_contents_with_options(PathBuf::new(), contents.to_string(), &options).unwrap();
+        assert_eq!(hotkeys[0].command, "Firefox --new-window");
+        assert_eq!(hotkeys[0].normalized_command, Some("firefox --new-window".to_string()));
+
+        let duplicates = find_duplicate_commands(&hotkeys);
+        assert_eq!(duplicates, vec![(hotkeys[0].clone(), hotkeys[1].clone())]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sticky_annotation() -> std::io::Result<()> {
         let contents = "
-super + {comma, period}
-	riverctl focus-output {previous, next}";
+super + r ; l [sticky]
+    bspc node -z right 20 0
+super + r ; {h,j} [sticky] [cancel=q]
+    bspc node -z {left,bottom} 20 0
+super + x
+    st";
+
+        let hotkeys = parse_contents(PathBuf::new(), contents.to_string()).unwrap();
+        assert_eq!(hotkeys.len(), 4);
+        assert!(hotkeys[0].sticky);
+        assert_eq!(hotkeys[0].cancel_key, evdev::Key::KEY_ESC);
+        for hotkey in &hotkeys[1..3] {
+            assert!(hotkey.sticky);
+            assert_eq!(hotkey.cancel_key, evdev::Key::KEY_Q);
+            assert_eq!(
+                hotkey.chord.as_ref().unwrap().0[0],
+                KeyBinding::new(evdev::Key::KEY_R, vec![Modifier::Super])
+            );
+        }
+        assert_eq!(hotkeys[1].keybinding, KeyBinding::new(evdev::Key::KEY_H, vec![]));
+        assert_eq!(hotkeys[1].command, "bspc node -z left 20 0");
+        assert!(!hotkeys[3].sticky);
+        Ok(())
+    }
 
-        eval_config_test(
-            contents,
-            vec![
-                Hotkey::new(
-                    evdev::Key::KEY_COMMA,
-                    vec![Modifier::Super],
-                    "riverctl focus-output previous".to_string(),
-                ),
-                Hotkey::new(
-                    evdev::Key::KEY_DOT,
-                    vec![Modifier::Super],
-                    "riverctl focus-output next".to_string(),
-                ),
-            ],
-        )
+    #[test]
+    fn test_sticky_annotation_without_chord() -> std::io::Result<()> {
+        let contents = "
+super + r [sticky]
+    st";
+
+        eval_invalid_config_test(contents, ParseError::InvalidAnnotation(PathBuf::new(), 2))
     }
 
     #[test]
-    fn test_period_escape_binding() -> std::io::Result<()> {
+    fn test_cancel_annotation_without_sticky() -> std::io::Result<()> {
         let contents = "
-super + {\\,, .}
-	riverctl focus-output {previous, next}";
+super + r [cancel=q]
+    st";
 
-        eval_config_test(
-            contents,
-            vec![
-                Hotkey::new(
-                    evdev::Key::KEY_COMMA,
-                    vec![Modifier::Super],
-                    "riverctl focus-output previous".to_string(),
-                ),
-                Hotkey::new(
-                    evdev::Key::KEY_DOT,
-                    vec![Modifier::Super],
-                    "riverctl focus-output next".to_string(),
-                ),
-            ],
+        eval_invalid_config_test(contents, ParseError::InvalidAnnotation(PathBuf::new(), 2))
+    }
+
+    #[test]
+    fn test_cancel_annotation_alias() -> std::io::Result<()> {
+        let contents = "
+alias leave = q
+super + r ; h [sticky] [cancel=leave]
+    st";
+
+        let hotkeys = parse_contents(PathBuf::new(), contents.to_string()).unwrap();
+        assert_eq!(hotkeys[0].cancel_key, evdev::Key::KEY_Q);
+        Ok(())
+    }
+
+    #[test]
+    fn test_cancel_annotation_strict_namespaces() -> std::io::Result<()> {
+        let options = ParseOptions { strict_namespaces: true, ..Default::default() };
+        let hotkeys = parse_contents_with_options(
+            PathBuf::new(),
+            "super + key:r ; key:h [sticky] [cancel=key:q]\n    st".to_string(),
+            &options,
         )
+        .unwrap();
+        assert_eq!(hotkeys[0].cancel_key, evdev::Key::KEY_Q);
+
+        let result = parse_contents_with_options(
+            PathBuf::new(),
+            "super + key:r ; key:h [sticky] [cancel=q]\n    st".to_string(),
+            &options,
+        );
+        assert!(matches!(result, Err(Error::InvalidConfig(ParseError::InvalidAnnotation(_, 1)))));
+        Ok(())
     }
 
     #[test]
-    fn test_prefix() -> std::io::Result<()> {
+    fn test_keysym_namespaces() -> std::io::Result<()> {
         let contents = "
-super + @1
-    1
-super + ~2
-    2
-super + ~@3
-    3
-super + @~4
-    4";
+super + key:a
+    st
+super + code:58
+    st
+super + btn:left
+    st
+super + btn:3
+    st
+            ";
+
+        let expected_hotkeys = vec![
+            Hotkey::new(evdev::Key::KEY_A, vec![Modifier::Super], "st".to_string()),
+            Hotkey::new(evdev::Key::KEY_CAPSLOCK, vec![Modifier::Super], "st".to_string()),
+            Hotkey::new(evdev::Key::BTN_LEFT, vec![Modifier::Super], "st".to_string()),
+            Hotkey::new(evdev::Key::BTN_RIGHT, vec![Modifier::Super], "st".to_string()),
+        ];
+
+        let options = ParseOptions { strict_namespaces: true, ..Default::default() };
+        let hotkeys =
+            parse_contents_with_options(PathBuf::new(), contents.to_string(), &options).unwrap();
+        assert_eq!(hotkeys, expected_hotkeys);
+
+        eval_config_test(contents, expected_hotkeys)
+    }
+
+    #[test]
+    fn test_unknown_name_in_namespace() -> std::io::Result<()> {
+        let contents = "
+super + key:pesto
+    st
+            ";
+
+        eval_invalid_config_test(contents, ParseError::InvalidKeysym(PathBuf::new(), 2))
+    }
+
+    #[test]
+    fn test_bare_keysym_in_strict_mode() -> std::io::Result<()> {
+        let contents = "
+super + a
+    st
+            ";
 
         eval_config_test(
             contents,
-            vec![
-                Hotkey::new(evdev::Key::KEY_1, vec![Modifier::Super], "1".to_string()).on_release(),
-                Hotkey::new(evdev::Key::KEY_2, vec![Modifier::Super], "2".to_string()).send(),
-                Hotkey::new(evdev::Key::KEY_3, vec![Modifier::Super], "3".to_string())
-                    .on_release()
-                    .send(),
-                Hotkey::new(evdev::Key::KEY_4, vec![Modifier::Super], "4".to_string())
-                    .on_release()
-                    .send(),
-            ],
-        )
+            vec![Hotkey::new(evdev::Key::KEY_A, vec![Modifier::Super], "st".to_string())],
+        )?;
+
+        let options = ParseOptions { strict_namespaces: true, ..Default::default() };
+        match parse_contents_with_options(PathBuf::new(), contents.to_string(), &options) {
+            Err(Error::InvalidConfig(parse_err)) => {
+                assert_eq!(parse_err, ParseError::InvalidKeysym(PathBuf::new(), 2))
+            }
+            result => panic!("Expected an invalid keysym error, found {:?}", result),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_config_strict_mode() -> std::io::Result<()> {
+        let setup = TestPath::new("/tmp/swhkd-test-strict1");
+        let mut f = File::create(setup.path())?;
+        f.write_all(b"include /tmp/swhkd-test-strict2\nsuper + key:a\n    st\n")?;
+
+        let setup2 = TestPath::new("/tmp/swhkd-test-strict2");
+        let mut f2 = File::create(setup2.path())?;
+        f2.write_all(b"super + b\n    ts\n")?;
+
+        assert_eq!(parse_config(&setup.path()).unwrap().len(), 2);
+
+        let options = ParseOptions { strict_namespaces: true, ..Default::default() };
+        match parse_config_with_options(&setup.path(), &options) {
+            Err(Error::InvalidConfig(parse_err)) => {
+                assert_eq!(parse_err, ParseError::InvalidKeysym(setup2.path(), 1))
+            }
+            result => panic!("Expected an invalid keysym error, found {:?}", result),
+        }
+        Ok(())
     }
 }
 
@@ -1151,6 +2700,26 @@ mod test_config_display {
         }
     }
 
+    #[test]
+    fn test_display_config_too_large_error() {
+        let error = Error::ConfigTooLarge(PathBuf::from("/dev/zero"), 1024);
+
+        assert_eq!(
+            format!("{}", error),
+            "Config file \"/dev/zero\" is larger than 1024 bytes, is it really a config file?"
+        );
+    }
+
+    #[test]
+    fn test_display_invalid_utf8_error() {
+        let error = Error::InvalidUtf8(PathBuf::from("/usr/bin/swhkd"));
+
+        assert_eq!(
+            format!("{}", error),
+            "Config file \"/usr/bin/swhkd\" is not valid UTF-8, is it really a config file?"
+        );
+    }
+
     #[test]
     fn test_display_unknown_symbol_error() {
         let error = Error::InvalidConfig(ParseError::UnknownSymbol(PathBuf::new(), 10));
@@ -1181,3 +2750,404 @@ mod test_config_display {
         );
     }
 }
+
+mod test_syntax {
+    use crate::syntax::{Item, SyntaxTree};
+
+    const CONTENTS: &str = "# Launch a terminal
+super + Return
+    alacritty
+
+include /etc/swhkd/extra
+
+  # volume
+XF86AudioRaiseVolume    # louder\t
+\tpamixer -i 5 ; \\
+\t   notify-send   'vol'
+super + \\
+shift + q
+    bspc node -c";
+
+    #[test]
+    fn test_round_trip() {
+        let tree = SyntaxTree::parse(CONTENTS);
+        assert_eq!(tree.bindings().count(), 3);
+        assert_eq!(tree.to_string(), CONTENTS);
+
+        let contents = format!("{}\n\n", CONTENTS);
+        assert_eq!(SyntaxTree::parse(&contents).to_string(), contents);
+        assert_eq!(SyntaxTree::parse("").to_string(), "");
+    }
+
+    #[test]
+    fn test_rename_keysym() {
+        let mut tree = SyntaxTree::parse(CONTENTS);
+        for binding in tree.bindings_mut() {
+            binding.rename_keysym("return", "KP_Enter");
+        }
+        assert_eq!(tree.to_string(), CONTENTS.replace("super + Return", "super + KP_Enter"));
+    }
+
+    #[test]
+    fn test_rename_keysym_in_chord_and_range() {
+        let contents = "super+r;{h,Return}\n    st\nsuper + {Return , a} ; Return\n    st";
+        let mut tree = SyntaxTree::parse(contents);
+        for binding in tree.bindings_mut() {
+            assert!(binding.rename_keysym("return", "KP_Enter"));
+        }
+        assert_eq!(
+            tree.to_string(),
+            "super+r;{h,KP_Enter}\n    st\nsuper + {KP_Enter , a} ; KP_Enter\n    st"
+        );
+    }
+
+    #[test]
+    fn test_trivia_before_command() {
+        let contents = "super + a\n\n# terminal\n    alacritty\nsuper + b\n# no command\n";
+        let tree = SyntaxTree::parse(contents);
+        assert_eq!(tree.to_string(), contents);
+
+        let bindings: Vec<_> = tree.bindings().collect();
+        assert_eq!(bindings.len(), 2);
+        assert_eq!(bindings[0].trivia.len(), 2);
+        assert_eq!(bindings[0].command[0].text, "    alacritty");
+        assert!(bindings[1].trivia.is_empty());
+        assert!(
+            matches!(tree.items.last(), Some(Item::Trivia(line)) if line.text == "# no command")
+        );
+    }
+
+    #[test]
+    fn test_swap_bindings() {
+        let contents = "a\n    st\n\n# b\nb\n    ts";
+        let mut tree = SyntaxTree::parse(contents);
+        assert_eq!(tree.swap_bindings(0, 1), Some(()));
+        assert_eq!(tree.to_string(), "b\n    ts\n\n# b\na\n    st");
+    }
+
+    #[test]
+    fn test_swap_bindings_out_of_range() {
+        let contents = "a\n    st\n\n# b\nb\n    ts";
+        let mut tree = SyntaxTree::parse(contents);
+        assert_eq!(tree.swap_bindings(0, 2), None);
+        assert_eq!(tree.swap_bindings(5, 1), None);
+        assert_eq!(tree.to_string(), contents);
+    }
+}
+
+mod test_plan {
+    use crate::config::{parse_contents, KeyBinding, Modifier};
+    use crate::plan::GrabPlan;
+    use evdev::Key;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_grab_plan() {
+        let contents = "
+super + Return
+    alacritty
+
+super + shift + Return
+    alacritty --class float
+
+ctrl + @q
+    kill
+
+~XF86AudioPlay
+    playerctl play-pause
+
+super
+    rofi -show drun
+
+super + r ; {h,l}
+    bspc node -z {left,right} 20 0
+
+super + c ; super + k
+    st";
+
+        let hotkeys = parse_contents(PathBuf::new(), contents.to_string()).unwrap();
+        let plan = GrabPlan::from(hotkeys);
+
+        assert_eq!(
+            plan.keys.iter().copied().collect::<Vec<_>>(),
+            vec![Key::KEY_Q, Key::KEY_ENTER, Key::KEY_PLAYPAUSE]
+        );
+        assert_eq!(
+            plan.modifiers.iter().copied().collect::<Vec<_>>(),
+            vec![Modifier::Super, Modifier::Control, Modifier::Shift]
+        );
+
+        assert_eq!(plan.buckets.len(), 3);
+        assert_eq!(plan.buckets[&Key::KEY_ENTER], vec![0, 1]);
+        assert_eq!(plan.buckets[&Key::KEY_Q], vec![2]);
+        let commands: Vec<&str> =
+            plan.hotkeys_for(Key::KEY_ENTER, "").map(|hotkey| hotkey.command.as_str()).collect();
+        assert_eq!(commands, vec!["alacritty", "alacritty --class float"]);
+        assert_eq!(plan.hotkeys_for(Key::KEY_A, "").count(), 0);
+        assert_eq!(plan.modifier_only, vec![4]);
+
+        assert!(plan.chords.hotkeys.is_empty());
+        assert_eq!(plan.chords.next.len(), 2);
+        let resize = &plan.chords.next[&KeyBinding::new(Key::KEY_R, vec![Modifier::Super])];
+        assert!(resize.hotkeys.is_empty());
+        assert_eq!(resize.next.len(), 2);
+        assert_eq!(resize.next[&KeyBinding::new(Key::KEY_H, vec![])].hotkeys, vec![5]);
+        assert_eq!(resize.next[&KeyBinding::new(Key::KEY_L, vec![])].hotkeys, vec![6]);
+        let close = &plan.chords.next[&KeyBinding::new(Key::KEY_C, vec![Modifier::Super])];
+        let kill = &close.next[&KeyBinding::new(Key::KEY_K, vec![Modifier::Super])];
+        assert_eq!(kill.hotkeys, vec![7]);
+        assert!(kill.next.is_empty());
+
+        assert!(plan.flags.on_release);
+        assert!(plan.flags.send);
+        assert!(!plan.flags.match_any_modifier);
+    }
+
+    #[test]
+    fn test_grab_plan_device() {
+        let contents = "
+super + Return
+    alacritty
+
+device \"Keychron K2\"
+super + Return
+    kitty
+
+super
+    rofi -show drun";
+
+        let hotkeys = parse_contents(PathBuf::new(), contents.to_string()).unwrap();
+        let plan = GrabPlan::from(hotkeys);
+
+        let commands = |device| {
+            plan.dispatchable(device).map(|hotkey| hotkey.command.as_str()).collect::<Vec<_>>()
+        };
+        assert_eq!(commands("Keychron K2"), vec!["alacritty", "kitty", "rofi -show drun"]);
+        assert_eq!(commands("AT Translated Set 2 keyboard"), vec!["alacritty"]);
+
+        let commands: Vec<&str> = plan
+            .hotkeys_for(Key::KEY_ENTER, "AT Translated Set 2 keyboard")
+            .map(|hotkey| hotkey.command.as_str())
+            .collect();
+        assert_eq!(commands, vec!["alacritty"]);
+        assert_eq!(plan.hotkeys_for(Key::KEY_ENTER, "Keychron K2").count(), 2);
+        assert_eq!(plan.modifier_only_hotkeys("Keychron K2").count(), 1);
+        assert_eq!(plan.modifier_only_hotkeys("AT Translated Set 2 keyboard").count(), 0);
+    }
+}
+
+#[cfg(feature = "serde")]
+mod test_json {
+    use crate::config::{hotkeys_to_json, parse_contents, Hotkey, Modifier, Prefix};
+    use crate::plan::GrabPlan;
+    use serde_json::{json, Value};
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_hotkeys_to_json() {
+        let hotkeys = vec![
+            Hotkey::new(
+                evdev::Key::KEY_ENTER,
+                vec![Modifier::Super, Modifier::Shift],
+                String::from("alacritty"),
+            ),
+            Hotkey::new(evdev::Key::KEY_Q, vec![Modifier::Control], String::from("kill")).send(),
+        ];
+
+        let json: Value = serde_json::from_str(&hotkeys_to_json(&hotkeys)).unwrap();
+        assert_eq!(
+            json,
+            json!([
+                {
+                    "keybinding": {
+                        "keysym": "KEY_ENTER",
+                        "modifiers": ["Super", "Shift"],
+                        "send": false,
+                        "on_release": false,
+                        "match_any_modifier": false,
+                        "repeat": 1,
+                    },
+                    "command": "alacritty",
+                    "cooldown_ms": null,
+                    "route": "Swhks",
+                    "normalized_command": null,
+                    "sticky": false,
+                    "cancel_key": "KEY_ESC",
+                    "device": null,
+                    "chord": null,
+                    "timeout_ms": null,
+                },
+                {
+                    "keybinding": {
+                        "keysym": "KEY_Q",
+                        "modifiers": ["Control"],
+                        "send": true,
+                        "on_release": false,
+                        "match_any_modifier": false,
+                        "repeat": 1,
+                    },
+                    "command": "kill",
+                    "cooldown_ms": null,
+                    "route": "Swhks",
+                    "normalized_command": null,
+                    "sticky": false,
+                    "cancel_key": "KEY_ESC",
+                    "device": null,
+                    "chord": null,
+                    "timeout_ms": null,
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn test_chord_trie_to_json() {
+        let contents = "
+super + r ; {h,l}
+    bspc node -z {left,right} 20 0";
+        let hotkeys = parse_contents(PathBuf::new(), contents.to_string()).unwrap();
+
+        let json = serde_json::to_value(GrabPlan::from(hotkeys)).unwrap();
+        let resize = &json["chords"]["next"][0];
+        assert_eq!(resize["keybinding"]["keysym"], json!("KEY_R"));
+        assert_eq!(resize["hotkeys"], json!([]));
+        assert_eq!(resize["next"][0]["keybinding"]["keysym"], json!("KEY_H"));
+        assert_eq!(resize["next"][1]["hotkeys"], json!([1]));
+    }
+
+    #[test]
+    fn test_unnamed_key_to_json() {
+        let hotkeys = vec![Hotkey::new(evdev::Key::new(700), vec![], String::from("st"))];
+
+        let json: Value = serde_json::from_str(&hotkeys_to_json(&hotkeys)).unwrap();
+        assert_eq!(json[0]["keybinding"]["keysym"], json!(700));
+        assert_eq!(json[0]["cancel_key"], json!("KEY_ESC"));
+    }
+}
+
+mod test_tap {
+    use crate::config::Modifier;
+    use crate::tap::ModifierTap;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_lone_modifier_tap() {
+        let mut tap = ModifierTap::default();
+        tap.press(Some(Modifier::Super));
+        assert_eq!(tap.release(Modifier::Super), Some(HashSet::from([Modifier::Super])));
+
+        tap.press(Some(Modifier::Control));
+        tap.press(Some(Modifier::Shift));
+        assert_eq!(tap.release(Modifier::Control), None);
+        assert_eq!(
+            tap.release(Modifier::Shift),
+            Some(HashSet::from([Modifier::Control, Modifier::Shift]))
+        );
+    }
+
+    #[test]
+    fn test_combo_is_not_a_tap() {
+        let mut tap = ModifierTap::default();
+        // super + a
+        tap.press(Some(Modifier::Super));
+        tap.press(None);
+        assert_eq!(tap.release(Modifier::Super), None);
+
+        // The next tap isn't affected by the combo before it
+        tap.press(Some(Modifier::Super));
+        assert_eq!(tap.release(Modifier::Super), Some(HashSet::from([Modifier::Super])));
+    }
+
+    #[test]
+    fn test_tap_after_typing() {
+        let mut tap = ModifierTap::default();
+        // ls followed by return
+        tap.press(None);
+        tap.press(None);
+        tap.press(None);
+
+        tap.press(Some(Modifier::Super));
+        assert_eq!(tap.release(Modifier::Super), Some(HashSet::from([Modifier::Super])));
+    }
+}
+
+mod test_simulate {
+    use crate::config::{parse_contents, Hotkey};
+    use crate::plan::GrabPlan;
+    use crate::simulate::Simulation;
+    use evdev::Key;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    fn plan(contents: &str) -> GrabPlan {
+        GrabPlan::from(parse_contents(PathBuf::new(), contents.to_string()).unwrap())
+    }
+
+    fn commands(hotkeys: Vec<&Hotkey>) -> Vec<&str> {
+        hotkeys.into_iter().map(|hotkey| hotkey.command.as_str()).collect()
+    }
+
+    const CONTENTS: &str = "
+super
+    rofi -show drun
+super + a
+    alacritty
+ctrl + @q
+    kill";
+
+    #[test]
+    fn test_lone_super_tap_fires() {
+        let plan = plan(CONTENTS);
+        let mut simulation = Simulation::new(&plan, "");
+        assert_eq!(commands(simulation.tap(Key::KEY_LEFTMETA)), vec!["rofi -show drun"]);
+    }
+
+    #[test]
+    fn test_combo_does_not_fire_the_tap() {
+        let plan = plan(CONTENTS);
+        let mut simulation = Simulation::new(&plan, "");
+        assert_eq!(simulation.press(Key::KEY_LEFTMETA), None);
+        assert_eq!(commands(simulation.tap(Key::KEY_A)), vec!["alacritty"]);
+        assert_eq!(simulation.release(Key::KEY_LEFTMETA), None);
+
+        // Typing in between doesn't swallow the next tap
+        assert!(simulation.tap(Key::KEY_L).is_empty());
+        assert_eq!(commands(simulation.tap(Key::KEY_RIGHTMETA)), vec!["rofi -show drun"]);
+    }
+
+    #[test]
+    fn test_double_tap() {
+        let plan = plan(
+            "
+ctrl
+    notify-send single
+ctrl*2 [timeout=200]
+    notify-send double",
+        );
+        let mut simulation = Simulation::new(&plan, "");
+        assert_eq!(commands(simulation.tap(Key::KEY_LEFTCTRL)), vec!["notify-send single"]);
+        simulation.wait(Duration::from_millis(100));
+        assert_eq!(commands(simulation.tap(Key::KEY_RIGHTCTRL)), vec!["notify-send double"]);
+
+        // Too slow, and a key pressed in between ends the row as well
+        simulation.wait(Duration::from_millis(100));
+        assert_eq!(commands(simulation.tap(Key::KEY_LEFTCTRL)), vec!["notify-send single"]);
+        simulation.wait(Duration::from_millis(300));
+        assert_eq!(commands(simulation.tap(Key::KEY_LEFTCTRL)), vec!["notify-send single"]);
+        simulation.tap(Key::KEY_A);
+        assert_eq!(commands(simulation.tap(Key::KEY_LEFTCTRL)), vec!["notify-send single"]);
+    }
+
+    #[test]
+    fn test_on_release_fires_on_release() {
+        let plan = plan(CONTENTS);
+        let mut simulation = Simulation::new(&plan, "");
+        simulation.press(Key::KEY_LEFTCTRL);
+        assert_eq!(simulation.press(Key::KEY_Q), None);
+        assert_eq!(
+            simulation.release(Key::KEY_Q).map(|hotkey| hotkey.command.as_str()),
+            Some("kill")
+        );
+        assert_eq!(simulation.release(Key::KEY_LEFTCTRL), None);
+    }
+}
@@ -16,7 +16,7 @@ pub fn create_uinput_device() -> Result<VirtualDevice, Box<dyn std::error::Error
     Ok(device)
 }
 pub fn get_all_keys() -> Vec<evdev::Key> {
-    return vec![
+    vec![
         evdev::Key::KEY_RESERVED,
         evdev::Key::KEY_ESC,
         evdev::Key::KEY_1,
@@ -565,5 +565,5 @@ pub fn get_all_keys() -> Vec<evdev::Key> {
         evdev::Key::BTN_TRIGGER_HAPPY38,
         evdev::Key::BTN_TRIGGER_HAPPY39,
         evdev::Key::BTN_TRIGGER_HAPPY40,
-    ];
+    ]
 }